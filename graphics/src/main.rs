@@ -1,5 +1,6 @@
-use std::sync::Arc;
+use std::{env, sync::Arc};
 
+use shared::package::Package;
 use winit::{
     application::ApplicationHandler,
     event::{KeyEvent, WindowEvent},
@@ -10,6 +11,7 @@ use winit::{
 
 struct State {
     window: Arc<Window>,
+    package: Option<Package>,
     surface: wgpu::Surface<'static>,
     device: wgpu::Device,
     queue: wgpu::Queue,
@@ -66,6 +68,7 @@ impl State {
 
         Ok(Self {
             window,
+            package: None,
             surface,
             device,
             queue,
@@ -92,6 +95,20 @@ impl State {
 
     fn update(&mut self) {}
 
+    fn load_level(&mut self, filename: &str) -> anyhow::Result<()> {
+        let package = Package::open(filename)?;
+        for entry in package.entries() {
+            log::info!(
+                "{:?} {} ({} bytes)",
+                entry.res_type,
+                entry.path,
+                entry.size()
+            );
+        }
+        self.package = Some(package);
+        Ok(())
+    }
+
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
         self.window.request_redraw();
 
@@ -138,11 +155,12 @@ impl State {
 
 struct App {
     state: Option<State>,
+    level: Option<String>,
 }
 
 impl App {
-    fn new() -> Self {
-        Self { state: None }
+    fn new(level: Option<String>) -> Self {
+        Self { state: None, level }
     }
 }
 
@@ -152,7 +170,13 @@ impl ApplicationHandler for App {
 
         let window = Arc::new(event_loop.create_window(window_attrs).unwrap());
 
-        self.state = Some(pollster::block_on(State::new(window)).unwrap());
+        let mut state = pollster::block_on(State::new(window)).unwrap();
+        if let Some(level) = &self.level
+            && let Err(e) = state.load_level(level)
+        {
+            log::error!("Unable to load {}: {}", level, e);
+        }
+        self.state = Some(state);
     }
 
     fn window_event(&mut self, event_loop: &ActiveEventLoop, window_id: WindowId, event: WindowEvent) {
@@ -190,7 +214,7 @@ fn main() -> anyhow::Result<()> {
     env_logger::init();
 
     let event_loop = EventLoop::new()?;
-    let mut app = App::new();
+    let mut app = App::new(env::args().nth(1));
     event_loop.run_app(&mut app)?;
 
     Ok(())
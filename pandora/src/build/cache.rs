@@ -0,0 +1,170 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::project::tasks::Task;
+
+/// Bump whenever converters or output formats change, so old cache entries are not reused.
+pub const CONVERTER_VERSION: u32 = 1;
+
+const CACHE_DIR: &str = ".pandora-cache";
+
+/// Converted entries stored under `<out_dir>/.pandora-cache/<package>/<key>`, shared between build workers.
+pub struct BuildCache {
+    dir: PathBuf,
+    force: bool,
+    used: Mutex<HashSet<String>>,
+    tmp_counter: AtomicUsize,
+}
+
+impl BuildCache {
+    pub fn open(out_dir: &Path, package_name: &str, force: bool) -> anyhow::Result<BuildCache> {
+        let dir = out_dir.join(CACHE_DIR).join(package_name);
+        fs::create_dir_all(&dir)?;
+        Ok(BuildCache {
+            dir,
+            force,
+            used: Mutex::new(HashSet::new()),
+            tmp_counter: AtomicUsize::new(0),
+        })
+    }
+
+    /// Deletes the cache of a package, returns its directory if there was one.
+    pub fn remove(out_dir: &Path, package_name: &str) -> anyhow::Result<Option<PathBuf>> {
+        let dir = out_dir.join(CACHE_DIR).join(package_name);
+        if !dir.exists() {
+            return Ok(None);
+        }
+        fs::remove_dir_all(&dir)?;
+        // Leave the shared cache folder alone while other packages still use it.
+        let _ = fs::remove_dir(out_dir.join(CACHE_DIR));
+        Ok(Some(dir))
+    }
+
+    /// Hash of everything the converted data depends on: source content, resolved params and converter version.
+    pub fn key(task: &Task, sources: &[PathBuf]) -> anyhow::Result<String> {
+        let mut hasher = Sha256::new();
+        hasher.update(CONVERTER_VERSION.to_le_bytes());
+        hasher.update(format!("{:?} {:?}", task.kind, task.src_ex).as_bytes());
+        for src in sources {
+            hasher.update(fs::read(src)?);
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    pub fn load(&self, key: &str) -> Option<Vec<u8>> {
+        self.mark_used(key);
+        if self.force {
+            return None;
+        }
+        fs::read(self.dir.join(key)).ok()
+    }
+
+    pub fn store(&self, key: &str, data: &[u8]) -> anyhow::Result<()> {
+        self.mark_used(key);
+        let tmp_id = self.tmp_counter.fetch_add(1, Ordering::Relaxed);
+        let tmp = self.dir.join(format!("{}.{}.tmp", key, tmp_id));
+        fs::write(&tmp, data)?;
+        fs::rename(tmp, self.dir.join(key))?;
+        Ok(())
+    }
+
+    fn mark_used(&self, key: &str) {
+        self.used.lock().unwrap().insert(key.to_string());
+    }
+
+    /// Removes entries that were not used by the last build.
+    pub fn prune(&self) -> anyhow::Result<()> {
+        let used = self.used.lock().unwrap();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            if !used.contains(name.to_string_lossy().as_ref()) {
+                fs::remove_file(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::DitheringMethod;
+
+    use super::*;
+    use crate::{
+        project::tasks::{SourceEx, TaskKind, TextureParams},
+        testing::TempDir,
+    };
+
+    fn texture(params: TextureParams) -> Task {
+        Task {
+            name: None,
+            src: String::from("flag.png"),
+            dest: String::from("/"),
+            kind: TaskKind::TextureConvert(params),
+            src_ex: SourceEx::Single,
+            sized: None,
+        }
+    }
+
+    #[test]
+    fn key_follows_source_and_params() {
+        let dir = TempDir::new("cache-key");
+        let sources = [dir.write("flag.png", [1, 2, 3])];
+        let task = texture(TextureParams::default());
+        let key = BuildCache::key(&task, &sources).unwrap();
+        assert_eq!(BuildCache::key(&task, &sources).unwrap(), key);
+
+        let dithered = texture(TextureParams {
+            dithering: DitheringMethod::FS,
+            ..TextureParams::default()
+        });
+        assert_ne!(BuildCache::key(&dithered, &sources).unwrap(), key);
+
+        dir.write("flag.png", [1, 2, 4]);
+        assert_ne!(BuildCache::key(&task, &sources).unwrap(), key);
+        assert!(BuildCache::key(&task, &[dir.path().join("missing.png")]).is_err());
+    }
+
+    #[test]
+    fn load_store_and_prune() {
+        let dir = TempDir::new("cache-store");
+        let cache = BuildCache::open(dir.path(), "p", false).unwrap();
+        assert_eq!(cache.load("a"), None);
+        cache.store("a", &[1, 2]).unwrap();
+        cache.store("b", &[3]).unwrap();
+        assert_eq!(cache.load("a"), Some(vec![1, 2]));
+
+        // The next build only uses `a`, `b` is dropped.
+        let cache = BuildCache::open(dir.path(), "p", false).unwrap();
+        assert_eq!(cache.load("a"), Some(vec![1, 2]));
+        cache.prune().unwrap();
+        let entries: Vec<_> = fs::read_dir(dir.path().join(CACHE_DIR).join("p"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(entries, ["a"]);
+
+        // Forced builds don't read the cache, but keep what they use.
+        let cache = BuildCache::open(dir.path(), "p", true).unwrap();
+        assert_eq!(cache.load("a"), None);
+        cache.prune().unwrap();
+        assert!(dir.path().join(CACHE_DIR).join("p/a").exists());
+
+        assert_eq!(
+            BuildCache::remove(dir.path(), "p").unwrap(),
+            Some(dir.path().join(CACHE_DIR).join("p"))
+        );
+        assert!(!dir.path().join(CACHE_DIR).exists());
+        assert_eq!(BuildCache::remove(dir.path(), "p").unwrap(), None);
+    }
+}
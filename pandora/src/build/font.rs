@@ -0,0 +1,292 @@
+use anyhow::{anyhow, bail};
+use image::DynamicImage;
+
+use crate::{build::convert_image, image::images::Image16, project::tasks::FontParams};
+
+/// Source rectangle of one character on the glyph sheet.
+struct Glyph {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+/// Converts a font image into a glyph sheet with per-character metrics, all numbers little-endian:
+///
+/// ```text
+/// start_char: u32, glyph_count: u32, fallback_char: u32, letter_space: i32, line_height: i32,
+/// glyphs: glyph_count times x: u16, y: u16, width: u16, height: u16
+/// sheet: texture
+/// ```
+///
+/// Characters fill the `cols`x`rows` grid row by row starting from `start_char`.
+pub fn convert_font(img: &DynamicImage, params: &FontParams) -> anyhow::Result<Vec<u8>> {
+    if params.cols == 0 || params.rows == 0 {
+        bail!("Font grid must have at least one column and one row");
+    }
+    if !img.width().is_multiple_of(params.cols) || !img.height().is_multiple_of(params.rows) {
+        bail!(
+            "Image size {}x{} can't be split into {}x{} cells",
+            img.width(),
+            img.height(),
+            params.cols,
+            params.rows
+        );
+    }
+    if img.width() > u16::MAX as u32 || img.height() > u16::MAX as u32 {
+        bail!("Font image is too large");
+    }
+    let cell_count = params
+        .cols
+        .checked_mul(params.rows)
+        .ok_or_else(|| anyhow!("Font grid {}x{} is too large", params.cols, params.rows))?;
+    let (Some(end_char), Some(fallback_char)) = (params.end_char, params.fallback_char) else {
+        bail!("Character range of a batch font isn't known before its frames are");
+    };
+    if params.start_char > end_char {
+        bail!(
+            "start_char {} is after end_char {}",
+            params.start_char,
+            end_char
+        );
+    }
+    let glyph_count = u64::from(end_char - params.start_char) + 1;
+    if glyph_count > u64::from(cell_count) {
+        bail!(
+            "Characters {}..{} need {} cells, the grid has only {}",
+            params.start_char,
+            end_char,
+            glyph_count,
+            cell_count
+        );
+    }
+    let glyph_count = glyph_count as u32;
+    if !(params.start_char..=end_char).contains(&fallback_char) {
+        bail!(
+            "fallback_char {} is outside of {}..{}",
+            fallback_char,
+            params.start_char,
+            end_char
+        );
+    }
+
+    let sheet = convert_image(img, params.dithering, params.transparent)?;
+    let cell_width = img.width() / params.cols;
+    let cell_height = img.height() / params.rows;
+
+    let top = params.border_top.unwrap_or(0);
+    let bottom = params.border_bottom.unwrap_or(0);
+    let glyph_height = top
+        .checked_add(bottom)
+        .and_then(|borders| cell_height.checked_sub(borders))
+        .filter(|&height| height > 0)
+        .ok_or_else(|| {
+            anyhow!(
+                "Top and bottom borders don't fit into {} pixel high cells",
+                cell_height
+            )
+        })?;
+    let line_height = if params.line_height > 0 {
+        params.line_height
+    } else {
+        glyph_height as i32
+    };
+
+    // Every cell lies inside the image, which is at most u16::MAX pixels wide and high,
+    // so the offsets below can't overflow.
+    let mut glyphs = Vec::with_capacity(glyph_count as usize);
+    for index in 0..glyph_count {
+        let cell_x = index % params.cols * cell_width;
+        let cell_y = index / params.cols * cell_height + top;
+        let (left, right) = match (params.border_left, params.border_right) {
+            (Some(left), Some(right)) => {
+                let right = cell_width
+                    .checked_sub(right)
+                    .filter(|&right| right > left)
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "Left and right borders don't fit into {} pixel wide cells",
+                            cell_width
+                        )
+                    })?;
+                (left, right)
+            }
+            _ => auto_borders(&sheet, cell_x, cell_y, cell_width, glyph_height),
+        };
+        glyphs.push(Glyph {
+            x: cell_x + left,
+            y: cell_y,
+            width: right - left,
+            height: glyph_height,
+        });
+    }
+
+    let mut data = Vec::new();
+    data.extend_from_slice(&params.start_char.to_le_bytes());
+    data.extend_from_slice(&glyph_count.to_le_bytes());
+    data.extend_from_slice(&fallback_char.to_le_bytes());
+    data.extend_from_slice(&params.letter_space.to_le_bytes());
+    data.extend_from_slice(&line_height.to_le_bytes());
+    for glyph in &glyphs {
+        for value in [glyph.x, glyph.y, glyph.width, glyph.height] {
+            data.extend_from_slice(&(value as u16).to_le_bytes());
+        }
+    }
+    sheet.write_bin(&mut data)?;
+    Ok(data)
+}
+
+/// Trims fully transparent columns on both sides of a cell, returns the kept column range.
+/// Empty cells (like space) and fonts without transparency keep the whole cell width.
+fn auto_borders(sheet: &Image16, cell_x: u32, cell_y: u32, width: u32, height: u32) -> (u32, u32) {
+    let Some(transparent_color) = sheet.transparent_color else {
+        return (0, width);
+    };
+    let is_empty = |col: u32| {
+        (0..height).all(|row| sheet.get(cell_x + col, cell_y + row) == transparent_color)
+    };
+    match (0..width).position(|col| !is_empty(col)) {
+        Some(left) => {
+            let right = (0..width).rposition(|col| !is_empty(col)).unwrap_or(left);
+            (left as u32, right as u32 + 1)
+        }
+        None => (0, width),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+    use shared::font::{Font, Glyph as FontGlyph};
+
+    use super::*;
+
+    /// Two 4x3 cells, the first with a glyph in its two middle columns, the second empty.
+    fn image() -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(8, 3, |x, _| match x {
+            1 | 2 => Rgba([255, 255, 255, 255]),
+            _ => Rgba([0, 0, 0, 0]),
+        }))
+    }
+
+    fn params() -> FontParams {
+        FontParams {
+            cols: 2,
+            rows: 1,
+            start_char: 'A' as u32,
+            end_char: Some('B' as u32),
+            fallback_char: Some('A' as u32),
+            ..FontParams::default()
+        }
+    }
+
+    fn glyph(x: u16, y: u16, width: u16, height: u16) -> FontGlyph {
+        FontGlyph {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn trims_transparent_columns() {
+        let font = Font::from_bytes(&convert_font(&image(), &params()).unwrap()).unwrap();
+        assert_eq!(font.start_char, 'A' as u32);
+        assert_eq!(font.line_height, 3);
+        assert_eq!(font.glyphs, [glyph(1, 0, 2, 3), glyph(4, 0, 4, 3)]);
+        assert_eq!((font.sheet.width, font.sheet.height), (8, 3));
+        // `?` falls back to `A`, glyphs are one pixel apart.
+        assert_eq!(font.text_width("AB?"), 10);
+    }
+
+    #[test]
+    fn fixed_borders() {
+        let params = FontParams {
+            border_left: Some(0),
+            border_right: Some(1),
+            border_top: Some(1),
+            border_bottom: Some(0),
+            line_height: 4,
+            ..params()
+        };
+        let font = Font::from_bytes(&convert_font(&image(), &params).unwrap()).unwrap();
+        assert_eq!(font.glyphs, [glyph(0, 1, 3, 2), glyph(4, 1, 3, 2)]);
+        assert_eq!(font.line_height, 4);
+    }
+
+    #[test]
+    fn bad_params() {
+        let error = |params: FontParams| convert_font(&image(), &params).unwrap_err().to_string();
+        assert_eq!(
+            error(FontParams {
+                cols: 3,
+                ..params()
+            }),
+            "Image size 8x3 can't be split into 3x1 cells"
+        );
+        assert_eq!(
+            error(FontParams {
+                end_char: Some('C' as u32),
+                ..params()
+            }),
+            "Characters 65..67 need 3 cells, the grid has only 2"
+        );
+        assert_eq!(
+            error(FontParams {
+                start_char: 'C' as u32,
+                ..params()
+            }),
+            "start_char 67 is after end_char 66"
+        );
+        assert_eq!(
+            error(FontParams {
+                fallback_char: Some('?' as u32),
+                ..params()
+            }),
+            "fallback_char 63 is outside of 65..66"
+        );
+        assert_eq!(
+            error(FontParams {
+                border_top: Some(2),
+                border_bottom: Some(1),
+                ..params()
+            }),
+            "Top and bottom borders don't fit into 3 pixel high cells"
+        );
+        assert_eq!(
+            error(FontParams {
+                border_left: Some(2),
+                border_right: Some(2),
+                ..params()
+            }),
+            "Left and right borders don't fit into 4 pixel wide cells"
+        );
+        assert_eq!(
+            error(FontParams {
+                end_char: None,
+                ..params()
+            }),
+            "Character range of a batch font isn't known before its frames are"
+        );
+    }
+
+    #[test]
+    fn reader_checks_glyphs() {
+        let mut data = convert_font(&image(), &params()).unwrap();
+        // Fallback character right after the font's range.
+        data[8..12].copy_from_slice(&('C' as u32).to_le_bytes());
+        assert_eq!(
+            Font::from_bytes(&data).unwrap_err().to_string(),
+            "Broken package entry: fallback character 67 is not in the font"
+        );
+
+        let mut data = convert_font(&image(), &params()).unwrap();
+        // Width of the second glyph, reaching past the sheet.
+        data[32..34].copy_from_slice(&5u16.to_le_bytes());
+        assert_eq!(
+            Font::from_bytes(&data).unwrap_err().to_string(),
+            "Broken package entry: glyph is outside of the font sheet"
+        );
+    }
+}
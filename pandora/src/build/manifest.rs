@@ -0,0 +1,249 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use path_slash::PathExt;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use shared::DitheringMethod;
+
+use crate::{
+    build::package::PackageEntry,
+    project::tasks::{SourceEx, Task, TaskKind},
+};
+
+/// Writes a Makefile rule making the package depend on every file it was built from.
+/// Each input also gets an empty rule, so make doesn't fail when one of them is deleted.
+pub fn write_depfile(path: &Path, package_file: &Path, inputs: &[PathBuf]) -> anyhow::Result<()> {
+    let mut text = format!("{}:", make_escape(package_file));
+    for input in inputs {
+        text.push_str(" \\\n  ");
+        text.push_str(&make_escape(input));
+    }
+    text.push('\n');
+    for input in inputs {
+        text.push_str(&format!("\n{}:\n", make_escape(input)));
+    }
+    fs::write(path, text)?;
+    Ok(())
+}
+
+fn make_escape(path: &Path) -> String {
+    path.to_slash_lossy()
+        .replace('$', "$$")
+        .replace('#', "\\#")
+        .replace(' ', "\\ ")
+}
+
+/// Describes one package entry for the JSON manifest: what it is, where it came from and what was written.
+pub fn manifest_entry(task: &Task, sources: &[PathBuf], entry: &PackageEntry) -> Value {
+    json!({
+        "name": entry.path.rsplit('/').next().unwrap_or_default(),
+        "type": format!("{:?}", entry.res_type).to_lowercase(),
+        "dest": entry.path,
+        "sources": sources.iter().map(|src| src.to_slash_lossy()).collect::<Vec<_>>(),
+        "params": params(task, sources.len()),
+        "size": entry.data.len(),
+        "sha256": format!("{:x}", Sha256::digest(&entry.data)),
+    })
+}
+
+pub fn write_manifest(
+    path: &Path,
+    package_name: &str,
+    package_file: &Path,
+    entries: Vec<Value>,
+) -> anyhow::Result<()> {
+    let manifest = json!({
+        "package": package_name,
+        "file": package_file.to_slash_lossy(),
+        "entries": entries,
+    });
+    fs::write(path, serde_json::to_string_pretty(&manifest)? + "\n")?;
+    Ok(())
+}
+
+/// Resolved conversion params, named like the properties in project files.
+/// Batch fonts get their rows and character range from the number of frames.
+fn params(task: &Task, source_count: usize) -> Value {
+    let mut params = match &task.kind {
+        TaskKind::TextureConvert(params) => json!({
+            "transparent": params.transparent,
+            "dither": dither_name(params.dithering),
+        }),
+        TaskKind::FontConvert(params) => {
+            let params = match task.src_ex {
+                SourceEx::Batch(_) => params.with_frames(source_count as u32),
+                _ => params.clone(),
+            };
+            json!({
+            "transparent": params.transparent,
+            "dither": dither_name(params.dithering),
+            "cols": params.cols,
+            "rows": params.rows,
+            // `null` borders are found automatically.
+            "borders": [params.border_left, params.border_right, params.border_top, params.border_bottom],
+            "start_char": params.start_char,
+            "end_char": params.end_char,
+            "fallback_char": params.fallback_char,
+            "letter_space": params.letter_space,
+            "line_height": params.line_height,
+            })
+        }
+        TaskKind::SpriteConvert(params) => json!({
+            "transparent": params.transparent,
+            "dither": dither_name(params.dithering),
+            "cols": params.cols,
+            "rows": params.rows,
+            "origin": [params.origin_x, params.origin_y],
+            "frame_time": params.frame_time,
+        }),
+        TaskKind::CopyFile(_) => json!({ "raw": true }),
+    };
+
+    if let SourceEx::Sheet(layout) | SourceEx::SheetCell(layout, _) = &task.src_ex {
+        params["sheet"] = json!({
+            "cols": layout.cols,
+            "rows": layout.rows,
+            "margin": layout.margin,
+            "padding": layout.padding,
+        });
+    }
+    if let SourceEx::SheetCell(_, index) = &task.src_ex {
+        params["cell"] = json!(index);
+    }
+    params
+}
+
+fn dither_name(dithering: DitheringMethod) -> &'static str {
+    match dithering {
+        DitheringMethod::No => "none",
+        DitheringMethod::FS => "fs",
+        DitheringMethod::Ord4 => "ord4",
+        DitheringMethod::Ord8 => "ord8",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::ResType;
+
+    use super::*;
+    use crate::{
+        project::{
+            batch::BatchRange,
+            tasks::{FontParams, SheetLayout, SpriteParams},
+        },
+        testing::TempDir,
+    };
+
+    fn task(kind: TaskKind, src_ex: SourceEx) -> Task {
+        Task {
+            name: None,
+            src: String::from("hero.png"),
+            dest: String::from("/"),
+            kind,
+            src_ex,
+            sized: None,
+        }
+    }
+
+    #[test]
+    fn depfile() {
+        let dir = TempDir::new("manifest-depfile");
+        let path = dir.path().join("p.d");
+        let inputs = [
+            PathBuf::from("test.pnd"),
+            PathBuf::from("my art/hero#2.png"),
+            PathBuf::from("$price.png"),
+        ];
+        write_depfile(&path, Path::new("build/p.pak"), &inputs).unwrap();
+        assert_eq!(
+            fs::read_to_string(path).unwrap(),
+            "build/p.pak: \\\n  test.pnd \\\n  my\\ art/hero\\#2.png \\\n  $$price.png\n\
+             \ntest.pnd:\n\nmy\\ art/hero\\#2.png:\n\n$$price.png:\n"
+        );
+    }
+
+    #[test]
+    fn sheet_cell_entry() {
+        let layout = SheetLayout {
+            cols: 4,
+            rows: 2,
+            margin: 1,
+            padding: 2,
+        };
+        let task = task(
+            TaskKind::SpriteConvert(SpriteParams::default()),
+            SourceEx::SheetCell(layout, 5),
+        );
+        let entry = PackageEntry {
+            path: String::from("/units/hero_5"),
+            res_type: ResType::Sprite,
+            data: b"abc".to_vec(),
+        };
+        let value = manifest_entry(&task, &[PathBuf::from("art/hero.png")], &entry);
+        assert_eq!(
+            value,
+            json!({
+                "name": "hero_5",
+                "type": "sprite",
+                "dest": "/units/hero_5",
+                "sources": ["art/hero.png"],
+                "params": {
+                    "transparent": true,
+                    "dither": "none",
+                    "cols": 1,
+                    "rows": 1,
+                    "origin": [0, 0],
+                    "frame_time": 1.0,
+                    "sheet": { "cols": 4, "rows": 2, "margin": 1, "padding": 2 },
+                    "cell": 5,
+                },
+                "size": 3,
+                "sha256": "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            })
+        );
+    }
+
+    #[test]
+    fn batch_font_params() {
+        let range = BatchRange {
+            start: None,
+            end: None,
+            step: 1,
+        };
+        let params = FontParams {
+            start_char: 48,
+            end_char: None,
+            fallback_char: None,
+            ..FontParams::default()
+        };
+        let task = task(TaskKind::FontConvert(params), SourceEx::Batch(range));
+        let params = super::params(&task, 20);
+        assert_eq!(params["rows"], 2);
+        assert_eq!(params["end_char"], 67);
+        assert_eq!(params["fallback_char"], 67);
+        assert_eq!(params["borders"], json!([null, null, null, null]));
+    }
+
+    #[test]
+    fn manifest_file() {
+        let dir = TempDir::new("manifest-file");
+        let path = dir.path().join("p.json");
+        let task = task(TaskKind::CopyFile(ResType::IntMap), SourceEx::Single);
+        let entry = PackageEntry {
+            path: String::from("/level"),
+            res_type: ResType::IntMap,
+            data: Vec::new(),
+        };
+        let entries = vec![manifest_entry(&task, &[PathBuf::from("level.int")], &entry)];
+        write_manifest(&path, "p", Path::new("build/p.pak"), entries).unwrap();
+        let manifest: Value = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(manifest["package"], "p");
+        assert_eq!(manifest["file"], "build/p.pak");
+        assert_eq!(manifest["entries"][0]["type"], "intmap");
+        assert_eq!(manifest["entries"][0]["params"], json!({ "raw": true }));
+    }
+}
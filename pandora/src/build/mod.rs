@@ -0,0 +1,517 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, anyhow};
+use image::{DynamicImage, ImageReader};
+use rayon::{ThreadPoolBuilder, prelude::*};
+use shared::{DitheringMethod, ResType};
+
+use crate::{
+    build::{
+        cache::BuildCache,
+        font::convert_font,
+        manifest::{manifest_entry, write_depfile, write_manifest},
+        package::{PackageEntry, PackageWriter},
+        sheet::{cell_size, join_grid, sheet_cell, split_sheet},
+        sprite::convert_sprite,
+    },
+    image::{
+        converters::{
+            convert_fs, convert_fs_transparent, convert_ordered4, convert_ordered4_transparent,
+            convert_ordered8, convert_ordered8_transparent, convert_posterize,
+            convert_posterize_transparent,
+        },
+        images::Image16,
+    },
+    project::{
+        batch::expand_batch,
+        tasks::{PackageTask, SheetLayout, SourceEx, Task, TaskKind},
+    },
+};
+
+mod cache;
+mod font;
+mod manifest;
+mod package;
+mod sheet;
+mod sprite;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+pub struct BuildOptions {
+    /// Ignore the build cache and convert every task again.
+    pub force: bool,
+    /// Number of worker threads, `None` uses one per CPU core.
+    pub jobs: Option<usize>,
+    pub verbosity: Verbosity,
+}
+
+/// Result of a single task, kept until all workers finish so the log and package order follow the task order.
+struct TaskOutput {
+    entry: PackageEntry,
+    log: String,
+    cached: bool,
+    /// Files the task was converted from.
+    sources: Vec<PathBuf>,
+    /// The task with its params that use the source image size evaluated.
+    task: Task,
+}
+
+/// What a build did, for callers that print their own report.
+pub struct BuildSummary {
+    pub package_file: PathBuf,
+    /// Tasks converted from their sources.
+    pub converted: usize,
+    /// Tasks taken from the build cache.
+    pub cached: usize,
+}
+
+pub fn build_package<P: AsRef<Path>>(
+    package: &PackageTask,
+    out_dir: P,
+    options: &BuildOptions,
+) -> anyhow::Result<BuildSummary> {
+    let out_dir = out_dir.as_ref();
+    if options.verbosity >= Verbosity::Normal {
+        println!("Package: {}", package.filename);
+    }
+
+    let cache = BuildCache::open(out_dir, &package.filename, options.force)?;
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(options.jobs.unwrap_or(0))
+        .build()?;
+    let outputs: Vec<anyhow::Result<TaskOutput>> = pool.install(|| {
+        package
+            .tasks
+            .par_iter()
+            .map(|task| {
+                run_task(task, &cache).with_context(|| format!("Failed to build \"{}\"", task.src))
+            })
+            .collect()
+    });
+
+    let mut writer = PackageWriter::new();
+    let mut cached = 0;
+    let mut inputs = package.project_files.clone();
+    let mut manifest = Vec::new();
+    for output in outputs {
+        let output = output?;
+        inputs.extend(output.sources.iter().cloned());
+        manifest.push(manifest_entry(&output.task, &output.sources, &output.entry));
+        if output.cached {
+            cached += 1;
+        }
+        match options.verbosity {
+            Verbosity::Quiet => {}
+            Verbosity::Normal => println!("    {}", output.log),
+            Verbosity::Verbose => {
+                println!("    {} ({} bytes)", output.log, output.entry.data.len())
+            }
+        }
+        writer.add(output.entry)?;
+    }
+
+    // Files included from several places and sources shared by tasks are listed once.
+    let mut seen = HashSet::new();
+    inputs.retain(|input| seen.insert(input.clone()));
+
+    let filename = output_file(out_dir, &package.filename, "pak");
+    writer.save(&filename)?;
+    write_depfile(
+        &output_file(out_dir, &package.filename, "d"),
+        &filename,
+        &inputs,
+    )?;
+    write_manifest(
+        &output_file(out_dir, &package.filename, "json"),
+        &package.filename,
+        &filename,
+        manifest,
+    )?;
+    cache.prune()?;
+    if options.verbosity >= Verbosity::Normal {
+        println!("Written {}", filename.display());
+    }
+    Ok(BuildSummary {
+        package_file: filename,
+        converted: package.tasks.len() - cached,
+        cached,
+    })
+}
+
+/// Removes the package file, its depfile, manifest and build cache, returns the paths that were deleted.
+pub fn clean_package(package_name: &str, out_dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    for extension in ["pak", "d", "json"] {
+        let filename = output_file(out_dir, package_name, extension);
+        if filename.exists() {
+            fs::remove_file(&filename)?;
+            removed.push(filename);
+        }
+    }
+    removed.extend(BuildCache::remove(out_dir, package_name)?);
+    Ok(removed)
+}
+
+/// `<out_dir>/<package>.pak` and the files written next to it: `.d` depfile and `.json` manifest.
+fn output_file(out_dir: &Path, package_name: &str, extension: &str) -> PathBuf {
+    let mut filename = out_dir.join(package_name);
+    filename.set_extension(extension);
+    filename
+}
+
+fn run_task(task: &Task, cache: &BuildCache) -> anyhow::Result<TaskOutput> {
+    let task = task.with_source_size()?;
+    let sources = resolve_sources(&task)?;
+    let res_type = match &task.kind {
+        TaskKind::TextureConvert(_) => ResType::Texture,
+        TaskKind::FontConvert(_) => ResType::Font,
+        TaskKind::SpriteConvert(_) => ResType::Sprite,
+        TaskKind::CopyFile(res_type) => *res_type,
+    };
+    let path = task.entry_path()?;
+    let label = match &task.src_ex {
+        SourceEx::Batch(_) => format!("{} ({} frames)", task.src, sources.len()),
+        _ => sources[0].display().to_string(),
+    };
+
+    let key = BuildCache::key(&task, &sources)?;
+    if let Some(data) = cache.load(&key) {
+        return Ok(TaskOutput {
+            log: format!("{} -> {} (cached)", label, path),
+            entry: PackageEntry {
+                path,
+                res_type,
+                data,
+            },
+            cached: true,
+            sources,
+            task,
+        });
+    }
+
+    let data = match (&task.kind, &task.src_ex) {
+        (TaskKind::TextureConvert(params), src_ex) => {
+            let mut img = open_image(&sources[0])?;
+            if let SourceEx::SheetCell(layout, index) = src_ex {
+                img = sheet_cell(&img, layout, *index)?;
+            }
+            let mut data = Vec::new();
+            convert_image(&img, params.dithering, params.transparent)?.write_bin(&mut data)?;
+            data
+        }
+        (TaskKind::FontConvert(params), SourceEx::Batch(_)) => {
+            let glyphs = open_images(&sources)?;
+            let params = params.with_frames(glyphs.len() as u32);
+            convert_font(&join_grid(&glyphs, params.cols)?, &params)?
+        }
+        (TaskKind::FontConvert(params), SourceEx::Sheet(layout)) => {
+            let glyphs = split_sheet(&open_image(&sources[0])?, layout)?;
+            convert_font(&join_grid(&glyphs, layout.cols)?, params)?
+        }
+        (TaskKind::FontConvert(params), _) => convert_font(&open_image(&sources[0])?, params)?,
+        (TaskKind::SpriteConvert(params), SourceEx::Batch(_)) => {
+            convert_sprite(&open_images(&sources)?, params)?
+        }
+        (TaskKind::SpriteConvert(params), src_ex) => {
+            let layout = match src_ex {
+                SourceEx::Sheet(layout) => layout.clone(),
+                _ => SheetLayout {
+                    cols: params.cols,
+                    rows: params.rows,
+                    margin: 0,
+                    padding: 0,
+                },
+            };
+            convert_sprite(&split_sheet(&open_image(&sources[0])?, &layout)?, params)?
+        }
+        (TaskKind::CopyFile(_), _) => fs::read(&sources[0])?,
+    };
+    cache.store(&key, &data)?;
+
+    Ok(TaskOutput {
+        log: format!("{} -> {}", label, path),
+        entry: PackageEntry {
+            path,
+            res_type,
+            data,
+        },
+        cached: false,
+        sources,
+        task,
+    })
+}
+
+fn open_image(src: &Path) -> anyhow::Result<DynamicImage> {
+    ImageReader::open(src)
+        .with_context(|| format!("Can't open \"{}\"", src.display()))?
+        .decode()
+        .with_context(|| format!("Can't decode \"{}\"", src.display()))
+}
+
+fn open_images(sources: &[PathBuf]) -> anyhow::Result<Vec<DynamicImage>> {
+    sources.iter().map(|src| open_image(src)).collect()
+}
+
+fn convert_image(
+    img: &DynamicImage,
+    dithering: DitheringMethod,
+    transparent: bool,
+) -> anyhow::Result<Image16> {
+    if transparent {
+        let dithering_method = match dithering {
+            DitheringMethod::No => convert_posterize_transparent,
+            DitheringMethod::FS => convert_fs_transparent,
+            DitheringMethod::Ord4 => convert_ordered4_transparent,
+            DitheringMethod::Ord8 => convert_ordered8_transparent,
+        };
+        dithering_method(&img.to_rgba8())
+    } else {
+        let dithering_method = match dithering {
+            DitheringMethod::No => convert_posterize,
+            DitheringMethod::FS => convert_fs,
+            DitheringMethod::Ord4 => convert_ordered4,
+            DitheringMethod::Ord8 => convert_ordered8,
+        };
+        Ok(dithering_method(&img.to_rgb8()))
+    }
+}
+
+/// Size of the image a task converts, the size of one cell for sheets and of the first frame for batches.
+pub fn source_size(src: &Path, src_ex: &SourceEx) -> anyhow::Result<(u32, u32)> {
+    let file = match src_ex {
+        SourceEx::Batch(range) => expand_batch(src, range)?.remove(0),
+        _ => src.to_path_buf(),
+    };
+    let context = || format!("Can't read the image size of \"{}\"", file.display());
+    let (width, height) = ImageReader::open(&file)
+        .with_context(context)?
+        .into_dimensions()
+        .with_context(context)?;
+    match src_ex {
+        SourceEx::Sheet(layout) | SourceEx::SheetCell(layout, _) => {
+            cell_size(width, height, layout)
+        }
+        _ => Ok((width, height)),
+    }
+}
+
+/// Files the task reads: every frame of a batch, or the single source file.
+/// Batch frames are looked up on disk here, when the task is built.
+pub fn resolve_sources(task: &Task) -> anyhow::Result<Vec<PathBuf>> {
+    match &task.src_ex {
+        SourceEx::Batch(range) => expand_batch(Path::new(&task.src), range),
+        _ => Ok(vec![resolve_source(task)?]),
+    }
+}
+
+/// Folder-level `from` paths point at a directory, the object name picks the file inside it.
+fn resolve_source(task: &Task) -> anyhow::Result<PathBuf> {
+    let src = PathBuf::from(&task.src);
+    if !src.is_dir() {
+        return Ok(src);
+    }
+
+    let name = task.name.as_ref().ok_or_else(|| {
+        anyhow!(
+            "Source \"{}\" is a directory, but object has no name",
+            task.src
+        )
+    })?;
+    let mut candidates = Vec::new();
+    for entry in fs::read_dir(&src)? {
+        let path = entry?.path();
+        if path.is_file() && path.file_stem().is_some_and(|stem| stem == name.as_str()) {
+            candidates.push(path);
+        }
+    }
+    candidates.sort();
+    candidates
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("No file named \"{}\" in \"{}\"", name, task.src))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use image::{Rgba, RgbaImage};
+    use shared::package::Package;
+
+    use super::*;
+    use crate::{project::project_from_file, testing::TempDir};
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+
+    fn options() -> BuildOptions {
+        BuildOptions {
+            force: false,
+            jobs: Some(2),
+            verbosity: Verbosity::Quiet,
+        }
+    }
+
+    /// Builds the project `source` in `dir` into `dir/build` and opens the package.
+    fn build(dir: &TempDir, source: &str, options: &BuildOptions) -> (BuildSummary, Package) {
+        let file = dir.write("test.pnd", source);
+        let package = project_from_file(&file, HashMap::new(), None).unwrap();
+        let summary = build_package(&package, dir.path().join("build"), options).unwrap();
+        let pak = Package::open(&summary.package_file).unwrap();
+        (summary, pak)
+    }
+
+    fn save_image(dir: &TempDir, name: &str, image: &RgbaImage) {
+        image.save(dir.path().join(name)).unwrap();
+    }
+
+    #[test]
+    fn converts_and_copies() {
+        let dir = TempDir::new("build-run");
+        save_image(
+            &dir,
+            "flag.png",
+            &RgbaImage::from_fn(2, 1, |x, _| [RED, BLUE][x as usize]),
+        );
+        dir.write("level.int", [1, 2, 3]);
+        let (summary, pak) = build(
+            &dir,
+            "package p {\n    tex flag (from \"flag.png\")\n    intmap level (from \"level.int\")\n}\n",
+            &options(),
+        );
+        assert_eq!(summary.package_file, dir.path().join("build/p.pak"));
+        assert_eq!((summary.converted, summary.cached), (2, 0));
+
+        let flag = pak.texture("/flag").unwrap();
+        assert_eq!(
+            (flag.width, flag.height, flag.transparent_color),
+            (2, 1, None)
+        );
+        assert_eq!(flag.data, [0xf800, 0x001f]);
+        assert_eq!(pak.data("/level").unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn rebuild_uses_cache() {
+        let dir = TempDir::new("build-cache");
+        save_image(&dir, "a.png", &RgbaImage::from_pixel(1, 1, RED));
+        save_image(&dir, "b.png", &RgbaImage::from_pixel(1, 1, RED));
+        let source = "package p {\n    tex a (from \"a.png\")\n    tex b (from \"b.png\")\n}\n";
+        build(&dir, source, &options());
+        let (summary, _) = build(&dir, source, &options());
+        assert_eq!((summary.converted, summary.cached), (0, 2));
+
+        save_image(&dir, "b.png", &RgbaImage::from_pixel(1, 1, BLUE));
+        let (summary, pak) = build(&dir, source, &options());
+        assert_eq!((summary.converted, summary.cached), (1, 1));
+        assert_eq!(pak.texture("/b").unwrap().data, [0x001f]);
+
+        let force = BuildOptions {
+            force: true,
+            ..options()
+        };
+        let (summary, _) = build(&dir, source, &force);
+        assert_eq!((summary.converted, summary.cached), (2, 0));
+    }
+
+    #[test]
+    fn parallel_build_keeps_project_order() {
+        let dir = TempDir::new("build-parallel");
+        let mut source = String::from("package p {\n");
+        for index in (0..16).rev() {
+            dir.write(&format!("data{}.int", index), [index as u8]);
+            source.push_str(&format!(
+                "    intmap d{} (from \"data{}.int\")\n",
+                index, index
+            ));
+        }
+        source.push_str("}\n");
+        let options = BuildOptions {
+            jobs: Some(4),
+            ..options()
+        };
+        let (_, pak) = build(&dir, &source, &options);
+        let paths: Vec<&str> = pak
+            .entries()
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .collect();
+        let expected: Vec<String> = (0..16).rev().map(|index| format!("/d{}", index)).collect();
+        assert_eq!(paths, expected);
+        assert_eq!(pak.data("/d3").unwrap(), [3]);
+    }
+
+    #[test]
+    fn parallel_build_reports_first_failure() {
+        let dir = TempDir::new("build-parallel-error");
+        let file = dir.write(
+            "test.pnd",
+            "package p {\n    tex a (from \"a.png\")\n    tex b (from \"b.png\")\n}\n",
+        );
+        let package = project_from_file(&file, HashMap::new(), None).unwrap();
+        let Err(err) = build_package(&package, dir.path().join("build"), &options()) else {
+            panic!("built without sources");
+        };
+        assert_eq!(
+            err.to_string(),
+            format!("Failed to build \"{}\"", dir.path().join("a.png").display())
+        );
+        assert!(!dir.path().join("build/p.pak").exists());
+    }
+
+    #[test]
+    fn sprite_frames_from_sheet() {
+        let dir = TempDir::new("build-sprite");
+        save_image(
+            &dir,
+            "hero.png",
+            &RgbaImage::from_fn(4, 1, |x, _| [RED, BLUE][x as usize / 2]),
+        );
+        let (_, pak) = build(
+            &dir,
+            "package p {\n    sprite hero (from \"hero.png\", cols 2, origin 1 0, fps 4)\n}\n",
+            &options(),
+        );
+        let hero = pak.sprite("/hero").unwrap();
+        assert_eq!(
+            (hero.origin_x, hero.origin_y, hero.frame_time),
+            (1, 0, 0.25)
+        );
+        let frames: Vec<_> = hero.frames.iter().map(|frame| frame.data.clone()).collect();
+        assert_eq!(frames, [[0xf800; 2], [0x001f; 2]]);
+    }
+
+    #[test]
+    fn writes_depfile_and_manifest() {
+        let dir = TempDir::new("build-depfile");
+        save_image(&dir, "flag.png", &RgbaImage::from_pixel(1, 1, RED));
+        let source =
+            "package p {\n    tex a (from \"flag.png\")\n    tex b (from \"flag.png\")\n}\n";
+        build(&dir, source, &options());
+
+        let depfile = fs::read_to_string(dir.path().join("build/p.d")).unwrap();
+        let inputs: Vec<&str> = depfile.lines().filter(|line| line.ends_with(':')).collect();
+        assert_eq!(inputs.len(), 2, "{}", depfile);
+        let manifest = fs::read_to_string(dir.path().join("build/p.json")).unwrap();
+        let manifest: serde_json::Value = serde_json::from_str(&manifest).unwrap();
+        assert_eq!(manifest["entries"][1]["dest"], "/b");
+
+        let removed = clean_package("p", &dir.path().join("build")).unwrap();
+        assert_eq!(removed.len(), 4);
+    }
+
+    #[test]
+    fn missing_source_names_the_file() {
+        let err = open_image(Path::new("missing/flag.png")).unwrap_err();
+        assert_eq!(err.to_string(), "Can't open \"missing/flag.png\"");
+    }
+}
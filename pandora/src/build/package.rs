@@ -0,0 +1,255 @@
+use std::{
+    collections::HashSet,
+    fs,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::anyhow;
+use shared::{
+    ResType,
+    package::{HEADER_SIZE, MAGIC, VERSION},
+};
+
+pub struct PackageEntry {
+    pub path: String,
+    pub res_type: ResType,
+    pub data: Vec<u8>,
+}
+
+pub struct PackageWriter {
+    entries: Vec<PackageEntry>,
+    paths: HashSet<String>,
+}
+
+impl PackageWriter {
+    pub fn new() -> PackageWriter {
+        PackageWriter {
+            entries: Vec::new(),
+            paths: HashSet::new(),
+        }
+    }
+
+    pub fn add(&mut self, entry: PackageEntry) -> anyhow::Result<()> {
+        if entry.path.len() > u16::MAX as usize {
+            return Err(anyhow!("Entry path \"{}\" is too long", entry.path));
+        }
+        if !self.paths.insert(entry.path.clone()) {
+            return Err(anyhow!("Duplicate package entry \"{}\"", entry.path));
+        }
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, filename: P) -> anyhow::Result<()> {
+        let mut file = BufWriter::new(fs::File::create(filename)?);
+        self.write(&mut file)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> anyhow::Result<()> {
+        let mut offsets = Vec::with_capacity(self.entries.len());
+        let mut offset = HEADER_SIZE;
+        for entry in &self.entries {
+            offsets.push(offset);
+            offset = u32::try_from(entry.data.len())
+                .ok()
+                .and_then(|size| offset.checked_add(size))
+                .ok_or_else(|| anyhow!("Package is too large"))?;
+        }
+        let directory_offset = offset;
+
+        out.write_all(&MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&0u16.to_le_bytes())?;
+        out.write_all(&(self.entries.len() as u32).to_le_bytes())?;
+        out.write_all(&directory_offset.to_le_bytes())?;
+
+        for entry in &self.entries {
+            out.write_all(&entry.data)?;
+        }
+
+        for (entry, offset) in self.entries.iter().zip(offsets) {
+            out.write_all(&entry.res_type.tag().to_le_bytes())?;
+            out.write_all(&(entry.path.len() as u16).to_le_bytes())?;
+            out.write_all(entry.path.as_bytes())?;
+            out.write_all(&offset.to_le_bytes())?;
+            out.write_all(&(entry.data.len() as u32).to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::package::{Package, PackageError};
+
+    use super::*;
+
+    fn entry(path: &str, res_type: ResType, data: &[u8]) -> PackageEntry {
+        PackageEntry {
+            path: path.to_string(),
+            res_type,
+            data: data.to_vec(),
+        }
+    }
+
+    fn package_bytes() -> Vec<u8> {
+        let mut writer = PackageWriter::new();
+        writer
+            .add(entry("/ui/logo", ResType::Texture, &[1, 2, 3]))
+            .unwrap();
+        writer.add(entry("/main", ResType::Font, &[])).unwrap();
+        writer
+            .add(entry("/coin", ResType::Sprite, &[4, 5]))
+            .unwrap();
+        writer
+            .add(entry("/maps/level", ResType::ExtMap, &[6]))
+            .unwrap();
+        let mut data = Vec::new();
+        writer.write(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn header_and_directory() {
+        let data = package_bytes();
+        assert_eq!(&data[0..4], b"HFPK");
+        assert_eq!(u16::from_le_bytes([data[4], data[5]]), VERSION);
+        assert_eq!(u32::from_le_bytes(data[8..12].try_into().unwrap()), 4);
+        let directory_offset = u32::from_le_bytes(data[12..16].try_into().unwrap());
+        assert_eq!(directory_offset, HEADER_SIZE + 6);
+        assert_eq!(data[directory_offset as usize], ResType::Texture.tag());
+    }
+
+    #[test]
+    fn round_trip() {
+        let package = Package::from_bytes(package_bytes()).unwrap();
+        let entries: Vec<_> = package
+            .entries()
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry.res_type, entry.size()))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("/ui/logo", ResType::Texture, 3),
+                ("/main", ResType::Font, 0),
+                ("/coin", ResType::Sprite, 2),
+                ("/maps/level", ResType::ExtMap, 1),
+            ]
+        );
+        assert_eq!(package.data("/ui/logo").unwrap(), [1, 2, 3]);
+        assert!(package.data("/main").unwrap().is_empty());
+        assert_eq!(package.data("/maps/level").unwrap(), [6]);
+        let logo = package.find("/ui/logo").unwrap();
+        assert_eq!((logo.folder(), logo.name()), ("/ui", "logo"));
+        let coin = package.find("/coin").unwrap();
+        assert_eq!((coin.folder(), coin.name()), ("/", "coin"));
+        assert!(matches!(
+            package.data("/missing"),
+            Err(PackageError::NotFound(_))
+        ));
+        assert!(matches!(
+            package.font("/coin"),
+            Err(PackageError::WrongType {
+                expected: ResType::Font,
+                found: ResType::Sprite,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn save_and_open() {
+        let filename =
+            std::env::temp_dir().join(format!("pandora-test-{}.pak", std::process::id()));
+        let mut writer = PackageWriter::new();
+        writer
+            .add(entry("/ui/logo", ResType::Texture, &[1, 2, 3]))
+            .unwrap();
+        writer.save(&filename).unwrap();
+        let package = Package::open(&filename);
+        fs::remove_file(&filename).unwrap();
+        assert_eq!(package.unwrap().data("/ui/logo").unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn writer_rejects_duplicates() {
+        let mut writer = PackageWriter::new();
+        writer.add(entry("/a", ResType::Texture, &[])).unwrap();
+        assert!(writer.add(entry("/a", ResType::Font, &[])).is_err());
+    }
+
+    #[test]
+    fn path_length_limit() {
+        let mut writer = PackageWriter::new();
+        let longest = format!("/{}", "a".repeat(u16::MAX as usize - 1));
+        writer.add(entry(&longest, ResType::IntMap, &[7])).unwrap();
+        let too_long = format!("/{}", "b".repeat(u16::MAX as usize));
+        assert!(writer.add(entry(&too_long, ResType::IntMap, &[])).is_err());
+
+        let mut data = Vec::new();
+        writer.write(&mut data).unwrap();
+        let package = Package::from_bytes(data).unwrap();
+        assert_eq!(package.data(&longest).unwrap(), [7]);
+    }
+
+    #[test]
+    fn bad_header() {
+        let mut data = package_bytes();
+        data[0] = b'X';
+        assert!(matches!(
+            Package::from_bytes(data),
+            Err(PackageError::BadMagic)
+        ));
+
+        let mut data = package_bytes();
+        data[4] = 2;
+        assert!(matches!(
+            Package::from_bytes(data),
+            Err(PackageError::UnsupportedVersion(2))
+        ));
+
+        assert!(matches!(
+            Package::from_bytes(b"HFPK".to_vec()),
+            Err(PackageError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn bad_directory() {
+        let data = package_bytes();
+        let directory_offset = HEADER_SIZE as usize + 6;
+
+        let truncated = data[..data.len() - 1].to_vec();
+        assert!(matches!(
+            Package::from_bytes(truncated),
+            Err(PackageError::Truncated)
+        ));
+
+        let mut unknown_tag = data.clone();
+        unknown_tag[directory_offset] = 0;
+        assert!(matches!(
+            Package::from_bytes(unknown_tag),
+            Err(PackageError::BadEntry(_))
+        ));
+
+        // The first entry's size is the last field of its directory record.
+        let size_pos = directory_offset + 1 + 2 + "/ui/logo".len() + 4;
+        let mut out_of_bounds = data.clone();
+        out_of_bounds[size_pos..size_pos + 4].copy_from_slice(&100u32.to_le_bytes());
+        assert!(matches!(
+            Package::from_bytes(out_of_bounds),
+            Err(PackageError::BadEntry(_))
+        ));
+
+        let mut trailing = data;
+        trailing.push(0);
+        assert!(matches!(
+            Package::from_bytes(trailing),
+            Err(PackageError::BadEntry(_))
+        ));
+    }
+}
@@ -0,0 +1,204 @@
+use anyhow::bail;
+use image::{DynamicImage, RgbaImage, imageops};
+
+use crate::project::tasks::SheetLayout;
+
+/// Cuts an image into the cells of `layout`, row by row, skipping the margin and the padding between cells.
+pub fn split_sheet(img: &DynamicImage, layout: &SheetLayout) -> anyhow::Result<Vec<DynamicImage>> {
+    let (cell_width, cell_height) = cell_size(img.width(), img.height(), layout)?;
+    Ok((0..layout.cell_count()?)
+        .map(|index| crop_cell(img, layout, cell_width, cell_height, index))
+        .collect())
+}
+
+/// Single cell of [`split_sheet`] without cropping the rest of the sheet.
+pub fn sheet_cell(
+    img: &DynamicImage,
+    layout: &SheetLayout,
+    index: u32,
+) -> anyhow::Result<DynamicImage> {
+    let (cell_width, cell_height) = cell_size(img.width(), img.height(), layout)?;
+    if index >= layout.cell_count()? {
+        bail!("Sheet has no cell {}", index);
+    }
+    Ok(crop_cell(img, layout, cell_width, cell_height, index))
+}
+
+/// Width and height of the cells of `layout` on a sheet image of the given size.
+pub fn cell_size(width: u32, height: u32, layout: &SheetLayout) -> anyhow::Result<(u32, u32)> {
+    if layout.cols == 0 || layout.rows == 0 {
+        bail!("Sheet must have at least one column and one row");
+    }
+    let inner = |size: u32, count: u32| {
+        let gaps = (count - 1)
+            .checked_mul(layout.padding)?
+            .checked_add(layout.margin.checked_mul(2)?)?;
+        size.checked_sub(gaps)
+            .filter(|&inner| inner > 0 && inner.is_multiple_of(count))
+            .map(|inner| inner / count)
+    };
+    match (inner(width, layout.cols), inner(height, layout.rows)) {
+        (Some(cell_width), Some(cell_height)) => Ok((cell_width, cell_height)),
+        _ => bail!(
+            "Image size {}x{} can't be split into {}x{} cells with margin {} and padding {}",
+            width,
+            height,
+            layout.cols,
+            layout.rows,
+            layout.margin,
+            layout.padding
+        ),
+    }
+}
+
+fn crop_cell(
+    img: &DynamicImage,
+    layout: &SheetLayout,
+    cell_width: u32,
+    cell_height: u32,
+    index: u32,
+) -> DynamicImage {
+    let col = index % layout.cols;
+    let row = index / layout.cols;
+    img.crop_imm(
+        layout.margin + col * (cell_width + layout.padding),
+        layout.margin + row * (cell_height + layout.padding),
+        cell_width,
+        cell_height,
+    )
+}
+
+/// Places equally sized cells into a grid `cols` wide, row by row. Unused cells of the last row stay transparent.
+pub fn join_grid(cells: &[DynamicImage], cols: u32) -> anyhow::Result<DynamicImage> {
+    let Some(first) = cells.first() else {
+        bail!("Nothing to put into the grid");
+    };
+    if cols == 0 {
+        bail!("Grid must have at least one column");
+    }
+    let (cell_width, cell_height) = (first.width(), first.height());
+    if let Some(other) = cells
+        .iter()
+        .find(|cell| cell.width() != cell_width || cell.height() != cell_height)
+    {
+        bail!(
+            "All images must have the same size, found {}x{} and {}x{}",
+            cell_width,
+            cell_height,
+            other.width(),
+            other.height()
+        );
+    }
+
+    let rows = (cells.len() as u32).div_ceil(cols);
+    let (Some(width), Some(height)) = (cols.checked_mul(cell_width), rows.checked_mul(cell_height))
+    else {
+        bail!(
+            "Grid of {}x{} cells of {}x{} pixels is too large",
+            cols,
+            rows,
+            cell_width,
+            cell_height
+        );
+    };
+    let mut sheet = RgbaImage::new(width, height);
+    for (index, cell) in (0..).zip(cells) {
+        imageops::replace(
+            &mut sheet,
+            &cell.to_rgba8(),
+            (index % cols * cell_width) as i64,
+            (index / cols * cell_height) as i64,
+        );
+    }
+    Ok(DynamicImage::ImageRgba8(sheet))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    fn layout(cols: u32, rows: u32, margin: u32, padding: u32) -> SheetLayout {
+        SheetLayout {
+            cols,
+            rows,
+            margin,
+            padding,
+        }
+    }
+
+    #[test]
+    fn cell_size_with_margin_and_padding() {
+        assert_eq!(cell_size(40, 10, &layout(4, 1, 0, 0)).unwrap(), (10, 10));
+        assert_eq!(cell_size(36, 14, &layout(3, 2, 1, 2)).unwrap(), (10, 5));
+    }
+
+    #[test]
+    fn cell_size_rejects_bad_layouts() {
+        assert!(cell_size(40, 10, &layout(0, 1, 0, 0)).is_err());
+        assert!(cell_size(40, 10, &layout(3, 1, 0, 0)).is_err());
+        assert!(cell_size(40, 10, &layout(4, 1, 20, 0)).is_err());
+        assert!(cell_size(40, 10, &layout(3, 1, 1, u32::MAX / 2)).is_err());
+        assert!(cell_size(40, 10, &layout(1, 1, u32::MAX, 0)).is_err());
+    }
+
+    #[test]
+    fn cell_count_limit() {
+        assert_eq!(layout(256, 256, 0, 0).cell_count().unwrap(), 65536);
+        assert!(layout(256, 257, 0, 0).cell_count().is_err());
+        assert!(layout(u32::MAX, 2, 0, 0).cell_count().is_err());
+    }
+
+    /// Sheet whose pixels hold their own coordinates in red and green.
+    fn coords(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+            Rgba([x as u8, y as u8, 0, 255])
+        }))
+    }
+
+    fn origin(cell: &DynamicImage) -> (u8, u8) {
+        let pixel = cell.to_rgba8()[(0, 0)];
+        (pixel[0], pixel[1])
+    }
+
+    #[test]
+    fn split_row_by_row() {
+        // Cells are 10x5, starting after a margin of 1 with 2 pixels between them.
+        let cells = split_sheet(&coords(36, 14), &layout(3, 2, 1, 2)).unwrap();
+        assert_eq!(cells.len(), 6);
+        assert!(
+            cells
+                .iter()
+                .all(|cell| (cell.width(), cell.height()) == (10, 5))
+        );
+        let origins: Vec<_> = cells.iter().map(origin).collect();
+        assert_eq!(
+            origins,
+            [(1, 1), (13, 1), (25, 1), (1, 8), (13, 8), (25, 8)]
+        );
+
+        let cell = sheet_cell(&coords(36, 14), &layout(3, 2, 1, 2), 4).unwrap();
+        assert_eq!(origin(&cell), (13, 8));
+        assert!(sheet_cell(&coords(36, 14), &layout(3, 2, 1, 2), 6).is_err());
+    }
+
+    #[test]
+    fn join_into_grid() {
+        let cells = split_sheet(&coords(30, 10), &layout(3, 1, 0, 0)).unwrap();
+        let grid = join_grid(&cells, 2).unwrap();
+        assert_eq!((grid.width(), grid.height()), (20, 20));
+        let grid = grid.to_rgba8();
+        assert_eq!(grid[(10, 0)], Rgba([10, 0, 0, 255]));
+        assert_eq!(grid[(0, 10)], Rgba([20, 0, 0, 255]));
+        assert_eq!(grid[(10, 10)], Rgba([0, 0, 0, 0]));
+
+        let err = join_grid(&[coords(2, 2), coords(2, 3)], 2).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "All images must have the same size, found 2x2 and 2x3"
+        );
+        assert!(join_grid(&[], 2).is_err());
+        assert!(join_grid(&cells, 0).is_err());
+    }
+}
@@ -0,0 +1,117 @@
+use anyhow::bail;
+use image::DynamicImage;
+
+use crate::{build::convert_image, project::tasks::SpriteParams};
+
+/// Converts each frame on its own so dithering patterns and transparent colors don't leak between frames.
+/// All numbers are little-endian:
+///
+/// ```text
+/// frame_count: u32, origin_x: i32, origin_y: i32, frame_time: f32,
+/// frames: frame_count times texture
+/// ```
+pub fn convert_sprite(frames: &[DynamicImage], params: &SpriteParams) -> anyhow::Result<Vec<u8>> {
+    let Some(first) = frames.first() else {
+        bail!("Sprite has no frames");
+    };
+    if frames
+        .iter()
+        .any(|frame| frame.width() != first.width() || frame.height() != first.height())
+    {
+        bail!("All sprite frames must have the same size");
+    }
+    if !params.frame_time.is_finite() || params.frame_time <= 0.0 {
+        bail!("Frame rate must be positive");
+    }
+
+    let mut data = Vec::new();
+    data.extend_from_slice(&(frames.len() as u32).to_le_bytes());
+    data.extend_from_slice(&params.origin_x.to_le_bytes());
+    data.extend_from_slice(&params.origin_y.to_le_bytes());
+    data.extend_from_slice(&params.frame_time.to_le_bytes());
+    for frame in frames {
+        convert_image(frame, params.dithering, params.transparent)?.write_bin(&mut data)?;
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+    use shared::sprite::Sprite;
+
+    use super::*;
+
+    fn frame(width: u32, height: u32, color: [u8; 3]) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, Rgb(color)))
+    }
+
+    fn params() -> SpriteParams {
+        SpriteParams {
+            transparent: false,
+            origin_x: 2,
+            origin_y: -1,
+            frame_time: 0.25,
+            ..SpriteParams::default()
+        }
+    }
+
+    #[test]
+    fn frames_in_order() {
+        let frames = [frame(2, 2, [255, 0, 0]), frame(2, 2, [0, 0, 255])];
+        let sprite = Sprite::from_bytes(&convert_sprite(&frames, &params()).unwrap()).unwrap();
+        assert_eq!((sprite.origin_x, sprite.origin_y), (2, -1));
+        assert_eq!(sprite.frame_time, 0.25);
+        assert_eq!(sprite.frames.len(), 2);
+        assert_eq!(sprite.frames[1].data, [0x001f; 4]);
+        // Loops after half a second.
+        assert_eq!(sprite.frame_at(0.3).data, sprite.frames[1].data);
+        assert_eq!(sprite.frame_at(0.6).data, sprite.frames[0].data);
+    }
+
+    #[test]
+    fn bad_frames() {
+        let error = |frames: &[DynamicImage], params: &SpriteParams| {
+            convert_sprite(frames, params).unwrap_err().to_string()
+        };
+        assert_eq!(error(&[], &params()), "Sprite has no frames");
+        assert_eq!(
+            error(&[frame(2, 2, [0; 3]), frame(2, 3, [0; 3])], &params()),
+            "All sprite frames must have the same size"
+        );
+        let stopped = SpriteParams {
+            frame_time: 0.0,
+            ..params()
+        };
+        assert_eq!(
+            error(&[frame(2, 2, [0; 3])], &stopped),
+            "Frame rate must be positive"
+        );
+    }
+
+    #[test]
+    fn reader_checks_frames() {
+        let mut data = convert_sprite(&[frame(1, 1, [0; 3])], &params()).unwrap();
+        data[0..4].copy_from_slice(&0u32.to_le_bytes());
+        assert_eq!(
+            Sprite::from_bytes(&data).unwrap_err().to_string(),
+            "Broken package entry: sprite has no frames"
+        );
+
+        let mut data = convert_sprite(&[frame(1, 1, [0; 3])], &params()).unwrap();
+        data[12..16].copy_from_slice(&f32::NAN.to_le_bytes());
+        assert_eq!(
+            Sprite::from_bytes(&data).unwrap_err().to_string(),
+            "Broken package entry: bad frame time NaN"
+        );
+
+        // Second frame of a different size appended by hand.
+        let mut data = convert_sprite(&[frame(1, 1, [0; 3])], &params()).unwrap();
+        data[0..4].copy_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(&[2, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            Sprite::from_bytes(&data).unwrap_err().to_string(),
+            "Broken package entry: sprite frames differ in size"
+        );
+    }
+}
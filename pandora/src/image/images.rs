@@ -1,4 +1,5 @@
 use image::RgbImage;
+use std::io::Write;
 
 use crate::image::colors::{Color16, ColorRGB};
 
@@ -27,6 +28,21 @@ impl Image16 {
         self.data[(x + y * self.width) as usize]
     }
 
+    pub fn write_bin<W: Write>(&self, out: &mut W) -> anyhow::Result<()> {
+        out.write_all(&self.width.to_le_bytes())?;
+        out.write_all(&self.height.to_le_bytes())?;
+        if let Some(transp_color) = self.transparent_color {
+            out.write_all(&1u8.to_le_bytes())?;
+            out.write_all(&transp_color.0.to_le_bytes())?;
+        } else {
+            out.write_all(&0u8.to_le_bytes())?;
+        }
+        for pixel in &self.data {
+            out.write_all(&pixel.0.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
     pub fn debug_save(self, filename: String) -> anyhow::Result<()> {
         let mut img = RgbImage::new(self.width, self.height);
         for (x, y, color) in img.enumerate_pixels_mut() {
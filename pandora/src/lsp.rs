@@ -0,0 +1,553 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::anyhow;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    DiagnosticSeverity, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents,
+    HoverParams, HoverProviderCapability, Location, MarkupContent, MarkupKind, OneOf, Position,
+    PublishDiagnosticsParams, Range, ServerCapabilities, TextDocumentSyncCapability,
+    TextDocumentSyncKind, Url,
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+        PublishDiagnostics,
+    },
+    request::{Completion, GotoDefinition, HoverRequest, Request as _},
+};
+use shared::ResType;
+
+use crate::project::{
+    ast::{Node, Span},
+    check_source,
+    diagnostics::{Diagnostic, Severity},
+    format::format_value,
+    lexer::Lexer,
+    parser::Token,
+    tasks::{object_params_at, referenced_file_at},
+    validate::{class_name, property_names, source_property_names, value_names},
+};
+
+const KEYWORDS: &[&str] = &[
+    "package", "include", "import", "preset", "define", "profile", "tex", "font", "sprite",
+    "intmap", "extmap",
+];
+
+/// Serves the Language Server Protocol on stdin and stdout until the editor shuts the server down.
+pub fn run_server() -> anyhow::Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![String::from("("), String::from(",")]),
+            ..Default::default()
+        }),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+    serve(connection)?;
+    io_threads.join()?;
+    Ok(())
+}
+
+/// Takes the connection by value, dropping it lets the writer thread finish.
+fn serve(connection: Connection) -> anyhow::Result<()> {
+    let mut server = Server {
+        documents: HashMap::new(),
+    };
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break;
+                }
+                connection
+                    .sender
+                    .send(Message::Response(server.request(request)))?;
+            }
+            Message::Notification(notification) => match server.notification(notification) {
+                Ok(Some(reply)) => connection.sender.send(Message::Notification(reply))?,
+                Ok(None) => {}
+                // A malformed notification only loses that one update, the session goes on.
+                Err(err) => eprintln!("Error: {:#}", err),
+            },
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+struct Server {
+    /// Text of the files open in the editor, including unsaved changes.
+    documents: HashMap<Url, String>,
+}
+
+impl Server {
+    fn request(&self, request: Request) -> Response {
+        let id = request.id.clone();
+        let result = match request.method.as_str() {
+            Completion::METHOD => handle::<Completion>(request, |params| self.completion(params)),
+            HoverRequest::METHOD => handle::<HoverRequest>(request, |params| self.hover(params)),
+            GotoDefinition::METHOD => {
+                handle::<GotoDefinition>(request, |params| self.definition(params))
+            }
+            method => {
+                let message = format!("Unknown method {}", method);
+                return Response::new_err(id, ErrorCode::MethodNotFound as i32, message);
+            }
+        };
+        match result {
+            Ok(value) => Response::new_ok(id, value),
+            Err(err) => {
+                Response::new_err(id, ErrorCode::RequestFailed as i32, format!("{:#}", err))
+            }
+        }
+    }
+
+    /// Keeps the documents up to date, answers every change with fresh diagnostics.
+    fn notification(&mut self, notification: Notification) -> anyhow::Result<Option<Notification>> {
+        let uri = match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams =
+                    notification.extract(DidOpenTextDocument::METHOD)?;
+                let uri = params.text_document.uri;
+                self.documents
+                    .insert(uri.clone(), params.text_document.text);
+                uri
+            }
+            DidChangeTextDocument::METHOD => {
+                let mut params: DidChangeTextDocumentParams =
+                    notification.extract(DidChangeTextDocument::METHOD)?;
+                let uri = params.text_document.uri;
+                // Full sync, the last change holds the whole text.
+                if let Some(change) = params.content_changes.pop() {
+                    self.documents.insert(uri.clone(), change.text);
+                }
+                uri
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams =
+                    notification.extract(DidCloseTextDocument::METHOD)?;
+                self.documents.remove(&params.text_document.uri);
+                params.text_document.uri
+            }
+            _ => return Ok(None),
+        };
+
+        let diagnostics = match self.documents.get(&uri) {
+            Some(text) => check_source(&file_path(&uri), text)
+                .1
+                .iter()
+                .map(|diagnostic| lsp_diagnostic(diagnostic, text))
+                .collect(),
+            None => Vec::new(),
+        };
+        Ok(Some(Notification::new(
+            PublishDiagnostics::METHOD.to_string(),
+            PublishDiagnosticsParams::new(uri, diagnostics, None),
+        )))
+    }
+
+    fn completion(&self, params: CompletionParams) -> anyhow::Result<Option<CompletionResponse>> {
+        let position = params.text_document_position;
+        let Some(text) = self.documents.get(&position.text_document.uri) else {
+            return Ok(None);
+        };
+        Ok(Some(CompletionResponse::Array(completions(
+            text,
+            position.position,
+        ))))
+    }
+
+    /// Shows an object the way it ends up after inheriting props from its package, folders and presets.
+    fn hover(&self, params: HoverParams) -> anyhow::Result<Option<Hover>> {
+        let position = params.text_document_position_params;
+        let Some((text, root, path)) = self.parse(&position.text_document.uri) else {
+            return Ok(None);
+        };
+        let Some(object) = object_params_at(&root, &path, span(text, position.position))? else {
+            return Ok(None);
+        };
+
+        let mut lines = vec![match &object.name {
+            Some(name) => format!("{} {}", class_name(object.res_type), name),
+            None => class_name(object.res_type).to_string(),
+        }];
+        lines.push(format!("dest \"{}\"", object.dest));
+        lines.push(format!("src \"{}\"", object.src.display()));
+        for (key, value) in &object.params {
+            let value = format_value(value);
+            lines.push(format!("{} {}", key, value).trim_end().to_string());
+        }
+        Ok(Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: format!("```\n{}\n```", lines.join("\n")),
+            }),
+            range: None,
+        }))
+    }
+
+    /// Jumps to the file of an `import` or `include`.
+    fn definition(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> anyhow::Result<Option<GotoDefinitionResponse>> {
+        let position = params.text_document_position_params;
+        let Some((text, root, path)) = self.parse(&position.text_document.uri) else {
+            return Ok(None);
+        };
+        let Some(file) = referenced_file_at(&root, &path, span(text, position.position))? else {
+            return Ok(None);
+        };
+        let Ok(file) = file.canonicalize() else {
+            return Ok(None);
+        };
+        let uri = Url::from_file_path(&file)
+            .map_err(|_| anyhow!("Can't turn {} into an URL", file.display()))?;
+        Ok(Some(GotoDefinitionResponse::Scalar(Location::new(
+            uri,
+            Range::default(),
+        ))))
+    }
+
+    fn parse(&self, uri: &Url) -> Option<(&str, Node, PathBuf)> {
+        let text = self.documents.get(uri)?;
+        let path = file_path(uri);
+        let root = check_source(&path, text).0?;
+        Some((text, root, path))
+    }
+}
+
+fn handle<R: lsp_types::request::Request>(
+    request: Request,
+    handler: impl FnOnce(R::Params) -> anyhow::Result<R::Result>,
+) -> anyhow::Result<serde_json::Value> {
+    let (_, params) = request.extract::<R::Params>(R::METHOD)?;
+    Ok(serde_json::to_value(handler(params)?)?)
+}
+
+fn file_path(uri: &Url) -> PathBuf {
+    uri.to_file_path()
+        .unwrap_or_else(|_| PathBuf::from(uri.path()))
+}
+
+/// Editor positions count from 0 in UTF-16 code units, spans from 1 in characters.
+fn span(text: &str, position: Position) -> Span {
+    let line = source_line(text, position.line);
+    let col = line[..byte_offset(line, position.character)]
+        .chars()
+        .count();
+    Span {
+        line: position.line + 1,
+        col: col as u32 + 1,
+    }
+}
+
+fn source_line(text: &str, line: u32) -> &str {
+    text.lines().nth(line as usize).unwrap_or_default()
+}
+
+/// Where the character at a UTF-16 offset starts in the line, the line length if it is past the end.
+fn byte_offset(line: &str, character: u32) -> usize {
+    let mut units = 0;
+    for (index, c) in line.char_indices() {
+        if units >= character {
+            return index;
+        }
+        units += c.len_utf16() as u32;
+    }
+    line.len()
+}
+
+fn utf16_len(text: &str) -> u32 {
+    text.chars().map(|c| c.len_utf16() as u32).sum()
+}
+
+/// Project diagnostics point at a column, the editor gets the word starting there underlined.
+fn lsp_diagnostic(diagnostic: &Diagnostic, text: &str) -> lsp_types::Diagnostic {
+    let line = diagnostic.line.saturating_sub(1);
+    let text_line = source_line(text, line);
+    let start = text_line
+        .char_indices()
+        .nth(diagnostic.col.saturating_sub(1) as usize)
+        .map_or(text_line.len(), |(start, _)| start);
+    let word = text_line[start..]
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .next()
+        .unwrap_or_default();
+    let col = utf16_len(&text_line[..start]);
+    let width = (word.len() as u32).max(1);
+    let message = match &diagnostic.help {
+        Some(help) => format!("{}\nhelp: {}", diagnostic.message, help),
+        None => diagnostic.message.clone(),
+    };
+    lsp_types::Diagnostic {
+        range: Range::new(Position::new(line, col), Position::new(line, col + width)),
+        severity: Some(match diagnostic.severity {
+            Severity::Error => DiagnosticSeverity::ERROR,
+            Severity::Warning => DiagnosticSeverity::WARNING,
+        }),
+        source: Some(String::from("pandora")),
+        message,
+        ..Default::default()
+    }
+}
+
+/// Open parenthesis the cursor is in.
+enum Frame {
+    /// Props of an object, or of a package, folder or preset (`None`).
+    Params(Option<ResType>),
+    /// Props of a `batch(...)` or `sheet(...)` source.
+    Source(String),
+}
+
+/// Keywords outside of parentheses, property names and their values inside.
+fn completions(text: &str, position: Position) -> Vec<CompletionItem> {
+    let before = text_before(text, position);
+    let mut lexer = Lexer::new(before);
+    let mut tokens = Vec::new();
+    while let Some(next) = lexer.next() {
+        match next {
+            Ok((token, ..)) => tokens.push(token),
+            // Most likely an unterminated string the cursor is in.
+            Err(_) => return Vec::new(),
+        }
+    }
+    if lexer
+        .into_comments()
+        .last()
+        .is_some_and(|comment| comment.span.line == position.line + 1)
+    {
+        return Vec::new();
+    }
+    // The word being typed is what the editor filters the completions by.
+    if before.ends_with(|c: char| c.is_ascii_alphanumeric() || c == '_') {
+        tokens.pop();
+    }
+
+    let mut frames = Vec::new();
+    let mut class = None;
+    for (index, token) in tokens.iter().enumerate() {
+        match token {
+            Token::KwTex(_) => class = Some(ResType::Texture),
+            Token::KwFont(_) => class = Some(ResType::Font),
+            Token::KwSprite(_) => class = Some(ResType::Sprite),
+            Token::KwIntMap(_) => class = Some(ResType::IntMap),
+            Token::KwExtMap(_) => class = Some(ResType::ExtMap),
+            Token::LParen => match index
+                .checked_sub(1)
+                .and_then(|previous| tokens.get(previous))
+            {
+                // `profile NAME (...)` overrides props of the object, like the params around it.
+                Some(Token::Name(_))
+                    if matches!(tokens.get(index.wrapping_sub(2)), Some(Token::KwProfile(_))) =>
+                {
+                    frames.push(Frame::Params(class))
+                }
+                Some(Token::Name((name, _))) if !frames.is_empty() => {
+                    frames.push(Frame::Source(name.clone()))
+                }
+                _ => frames.push(Frame::Params(class)),
+            },
+            Token::RParen => {
+                frames.pop();
+            }
+            Token::LBracket | Token::RBracket(_) => {
+                frames.clear();
+                class = None;
+            }
+            Token::Str(_) | Token::KwPackage(_) | Token::KwPreset | Token::KwProfile(_)
+                if frames.is_empty() =>
+            {
+                class = None
+            }
+            _ => {}
+        }
+    }
+
+    let previous = tokens.last();
+    let key_position = matches!(previous, Some(Token::LParen | Token::Comma));
+    match frames.last() {
+        None => KEYWORDS
+            .iter()
+            .map(|keyword| item(keyword, CompletionItemKind::KEYWORD, None))
+            .collect(),
+        Some(Frame::Params(res_type)) if key_position => property_names(*res_type)
+            .into_iter()
+            .chain([
+                ("use", "a preset name"),
+                ("profile", "a profile name and props"),
+            ])
+            .map(|(name, detail)| item(name, CompletionItemKind::PROPERTY, Some(detail)))
+            .collect(),
+        Some(Frame::Params(_)) => match previous {
+            Some(Token::KwUse) => preset_names(text)
+                .iter()
+                .map(|name| item(name, CompletionItemKind::REFERENCE, None))
+                .collect(),
+            Some(Token::Name((key, _)))
+                if matches!(
+                    tokens.iter().nth_back(1),
+                    Some(Token::LParen | Token::Comma)
+                ) =>
+            {
+                value_names(key)
+                    .iter()
+                    .map(|name| item(name, CompletionItemKind::ENUM_MEMBER, None))
+                    .collect()
+            }
+            _ => Vec::new(),
+        },
+        Some(Frame::Source(source)) if key_position => source_property_names(source)
+            .into_iter()
+            .map(|(name, detail)| item(name, CompletionItemKind::PROPERTY, Some(detail)))
+            .collect(),
+        Some(Frame::Source(_)) => Vec::new(),
+    }
+}
+
+fn item(label: &str, kind: CompletionItemKind, detail: Option<&str>) -> CompletionItem {
+    CompletionItem {
+        label: label.to_string(),
+        kind: Some(kind),
+        detail: detail.map(str::to_string),
+        ..Default::default()
+    }
+}
+
+/// Text from the start of the document up to the cursor.
+fn text_before(text: &str, position: Position) -> &str {
+    let mut offset = 0;
+    for (index, line) in text.split_inclusive('\n').enumerate() {
+        if index == position.line as usize {
+            let line = line.trim_end_matches(['\n', '\r']);
+            return &text[..offset + byte_offset(line, position.character)];
+        }
+        offset += line.len();
+    }
+    text
+}
+
+fn preset_names(text: &str) -> Vec<String> {
+    let mut lexer = Lexer::new(text);
+    let mut names = Vec::new();
+    let mut after_preset = false;
+    while let Some(Ok((token, ..))) = lexer.next() {
+        if let (true, Token::Name((name, _))) = (after_preset, &token) {
+            names.push(name.clone());
+        }
+        after_preset = matches!(token, Token::KwPreset);
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Completion labels at the `|` in `text`.
+    fn labels(text: &str) -> Vec<String> {
+        let (before, after) = text.split_once('|').unwrap();
+        let line = before.matches('\n').count() as u32;
+        let character = utf16_len(before.rsplit('\n').next().unwrap());
+        completions(
+            &format!("{}{}", before, after),
+            Position::new(line, character),
+        )
+        .into_iter()
+        .map(|item| item.label)
+        .collect()
+    }
+
+    #[test]
+    fn keyword_completions() {
+        assert_eq!(labels("package p {\n    |\n}"), KEYWORDS);
+        assert_eq!(labels("package p {\n    te|\n}"), KEYWORDS);
+        assert_eq!(
+            labels("package p {\n    tex a (dither fs)\n    |"),
+            KEYWORDS
+        );
+        assert!(labels("package p { # te|").is_empty());
+    }
+
+    #[test]
+    fn property_completions() {
+        let font = labels("package p { font a (|) }");
+        assert!(font.iter().any(|label| label == "start_char"));
+        assert!(font.iter().any(|label| label == "use"));
+        assert!(!font.iter().any(|label| label == "origin"));
+
+        let texture = labels("package p { tex a (from \"a.png\", di|) }");
+        assert!(texture.iter().any(|label| label == "dither"));
+        assert!(!texture.iter().any(|label| label == "start_char"));
+
+        // Folders take the props of every class.
+        let folder = labels("package p { \"/ui\" (|) {} }");
+        assert!(folder.iter().any(|label| label == "origin"));
+        assert!(folder.iter().any(|label| label == "start_char"));
+    }
+
+    #[test]
+    fn source_property_completions() {
+        assert_eq!(
+            labels("package p { tex a (from sheet(file \"a.png\", |)) }"),
+            ["file", "cols", "rows", "margin", "padding", "name"]
+        );
+        assert_eq!(
+            labels("package p { tex a (from batch(|)) }"),
+            ["file", "start", "end", "step"]
+        );
+        // Back in the object props after the source closes.
+        let after = labels("package p { tex a (from batch(file \"a%d\"), |) }");
+        assert!(after.iter().any(|label| label == "dither"));
+    }
+
+    #[test]
+    fn value_completions() {
+        assert_eq!(
+            labels("package p { tex a (dither |) }"),
+            ["none", "fs", "ord4", "ord8"]
+        );
+        assert_eq!(
+            labels("package p { tex a (from sheet(file \"a.png\"), dither o|) }"),
+            ["none", "fs", "ord4", "ord8"]
+        );
+        assert_eq!(labels("package p { tex a (from |) }"), ["batch", "sheet"]);
+        assert!(labels("package p { tex a (cols 2 |) }").is_empty());
+        assert_eq!(
+            labels(
+                "package p {\n    preset big (cols 4)\n    preset small (cols 1)\n    tex a (use |)\n}"
+            ),
+            ["big", "small"]
+        );
+    }
+
+    #[test]
+    fn text_up_to_cursor() {
+        let text = "package p {\r\n    tex \"ä😀\" ()\r\n}";
+        assert_eq!(text_before(text, Position::new(0, 7)), "package");
+        assert_eq!(text_before(text, Position::new(0, 40)), "package p {");
+        // `😀` takes two UTF-16 code units.
+        assert_eq!(
+            text_before(text, Position::new(1, 13)),
+            "package p {\r\n    tex \"ä😀\""
+        );
+        assert_eq!(text_before(text, Position::new(5, 0)), text);
+    }
+
+    #[test]
+    fn span_of_utf16_position() {
+        let text = "package p {\n    tex \"😀\" a\n}";
+        let span = span(text, Position::new(1, 13));
+        assert_eq!((span.line, span.col), (2, 13));
+    }
+
+    #[test]
+    fn presets() {
+        let text = "package p {\n    preset big (cols 4)\n    \"/ui\" (use big) {\n        preset small\n    }\n}";
+        assert_eq!(preset_names(text), ["big", "small"]);
+    }
+}
@@ -1,28 +1,258 @@
-use std::fs;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
-use crate::project::project_from_file;
+use anyhow::{Context, anyhow, bail};
+use clap::{Parser, Subcommand};
 
+use crate::{
+    build::{BuildOptions, Verbosity, build_package, clean_package},
+    project::{ast::Node, ast_from_file, format_file, project_from_file},
+};
+
+mod build;
 mod image;
+mod lsp;
 mod project;
+#[cfg(test)]
+mod testing;
+#[cfg(target_os = "linux")]
+mod watch;
 
-fn main() -> anyhow::Result<()> {
-    /*match env::current_dir() {
-        Ok(path) => println!("The current working directory is: {}", path.display()),
-        Err(e) => eprintln!("Failed to get current working directory: {}", e),
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "Builds hellforge resource packages from .pnd project files"
+)]
+struct ArgMain {
+    #[command(subcommand)]
+    command: Command,
+    /// Directory for packages and the build cache, defaults to "build" next to the project file
+    #[arg(short, long, global = true)]
+    output: Option<PathBuf>,
+    /// Print more details
+    #[arg(short, long, global = true, conflicts_with = "quiet")]
+    verbose: bool,
+    /// Print nothing but warnings and errors
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    /// Number of parallel jobs, defaults to the number of CPU cores
+    #[arg(short, long, global = true)]
+    jobs: Option<usize>,
+    /// Set NAME for ${NAME} in project strings, overriding defines and environment variables
+    #[arg(short = 'D', global = true, value_name = "NAME=VALUE", value_parser = parse_define)]
+    define: Vec<(String, String)>,
+    /// Build profile, enables the matching `profile` blocks and property overrides of the project
+    #[arg(short, long, global = true)]
+    profile: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Convert all resources and write the package
+    Build {
+        project: PathBuf,
+        /// Rebuild every task, ignoring the build cache
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Build, then rebuild whenever the project files or the resource sources change
+    #[cfg(target_os = "linux")]
+    Watch {
+        project: PathBuf,
+        /// Rebuild every task on the first build, ignoring the build cache
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Parse and validate the project and resolve its tasks without converting anything
+    Check { project: PathBuf },
+    /// Print the resolved tasks
+    List { project: PathBuf },
+    /// Remove the package and its build cache
+    Clean { project: PathBuf },
+    /// Print the syntax tree of a project file
+    DumpAst { project: PathBuf },
+    /// Run a language server for editors, speaking LSP on stdin and stdout
+    Lsp,
+    /// Rewrite project files in canonical formatting
+    Fmt {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Only report files that are not formatted, exit with an error if there are any
+        #[arg(long)]
+        check: bool,
+    },
+}
+
+fn parse_define(text: &str) -> Result<(String, String), String> {
+    match text.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(String::from("expected NAME=VALUE")),
+    }
+}
+
+impl ArgMain {
+    fn verbosity(&self) -> Verbosity {
+        if self.quiet {
+            Verbosity::Quiet
+        } else if self.verbose {
+            Verbosity::Verbose
+        } else {
+            Verbosity::Normal
+        }
     }
 
-    let img = ImageReader::open("assets/transp1.png")?.decode()?.to_rgba8();
-    let img16 = convert_ordered8_transparent(&img)?;
-    img16.debug_save(String::from("assets/transp1_prc.png"))?;
-    return Ok(());*/
+    fn out_dir(&self, project: &Path) -> PathBuf {
+        match &self.output {
+            Some(output) => output.clone(),
+            None => project.parent().unwrap_or(Path::new("")).join("build"),
+        }
+    }
+}
 
-    let package = project_from_file("pandora/test.pnd")?;
+fn main() -> anyhow::Result<()> {
+    let args = ArgMain::parse();
+    let verbosity = args.verbosity();
+    let defines = || args.define.iter().cloned().collect();
+    let profile = args.profile.as_deref();
 
-    println!("Package: {}", package.filename);
-    println!("Tasks:");
-    for task in package.tasks {
-        println!("    {:?}", task);
+    match &args.command {
+        Command::Build { project, force } => {
+            let package = project_from_file(project, defines(), profile)?;
+            build_package(
+                &package,
+                args.out_dir(project),
+                &BuildOptions {
+                    force: *force,
+                    jobs: args.jobs,
+                    verbosity,
+                },
+            )?;
+        }
+        #[cfg(target_os = "linux")]
+        Command::Watch { project, force } => watch::watch_package(
+            project,
+            &defines(),
+            profile,
+            &args.out_dir(project),
+            &BuildOptions {
+                force: *force,
+                jobs: args.jobs,
+                verbosity,
+            },
+        )?,
+        Command::Check { project } => {
+            let package = project_from_file(project, defines(), profile)?;
+            if verbosity >= Verbosity::Normal {
+                println!(
+                    "{}: {} tasks, no errors",
+                    project.display(),
+                    package.tasks.len()
+                );
+            }
+        }
+        Command::List { project } => {
+            let package = project_from_file(project, defines(), profile)?;
+            for task in &package.tasks {
+                println!("{} -> {}", task.src, task.entry_path()?);
+                if verbosity == Verbosity::Verbose {
+                    println!("    {:?} {:?}", task.kind, task.src_ex);
+                }
+            }
+        }
+        Command::Clean { project } => {
+            let Node::Package(name, ..) = ast_from_file(project)? else {
+                return Err(anyhow!("{} is not a package", project.display()));
+            };
+            for path in clean_package(&name, &args.out_dir(project))? {
+                if verbosity >= Verbosity::Normal {
+                    println!("Removed {}", path.display());
+                }
+            }
+        }
+        Command::DumpAst { project } => println!("{:#?}", ast_from_file(project)?),
+        Command::Lsp => lsp::run_server()?,
+        Command::Fmt { files, check } => {
+            let mut unformatted = 0;
+            for file in files {
+                let (source, formatted) = format_file(file)?;
+                if source == formatted {
+                    continue;
+                }
+                if *check {
+                    println!("Would reformat {}", file.display());
+                    unformatted += 1;
+                } else {
+                    fs::write(file, formatted)
+                        .with_context(|| format!("Can't write {}", file.display()))?;
+                    if verbosity >= Verbosity::Normal {
+                        println!("Formatted {}", file.display());
+                    }
+                }
+            }
+            if unformatted > 0 {
+                bail!("{} of {} files are not formatted", unformatted, files.len());
+            }
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<ArgMain, clap::Error> {
+        ArgMain::try_parse_from([&["pandora"], args].concat())
+    }
+
+    #[test]
+    fn define_argument() {
+        assert_eq!(
+            parse_define("DIR=art/ui"),
+            Ok((String::from("DIR"), String::from("art/ui")))
+        );
+        assert_eq!(
+            parse_define("EQ=a=b"),
+            Ok((String::from("EQ"), String::from("a=b")))
+        );
+        assert_eq!(
+            parse_define("EMPTY="),
+            Ok((String::from("EMPTY"), String::new()))
+        );
+        assert!(parse_define("DIR").is_err());
+        assert!(parse_define("=art").is_err());
+    }
+
+    #[test]
+    fn global_options_after_command() {
+        let args = parse(&["build", "game/test.pnd", "-f", "-v", "-j", "4", "-D", "A=1"]).unwrap();
+        assert!(matches!(args.command, Command::Build { force: true, .. }));
+        assert_eq!(args.verbosity(), Verbosity::Verbose);
+        assert_eq!(args.jobs, Some(4));
+        assert_eq!(args.define, [(String::from("A"), String::from("1"))]);
+        assert_eq!(
+            args.out_dir(Path::new("game/test.pnd")),
+            Path::new("game/build")
+        );
+
+        let args = parse(&["-q", "-o", "out", "check", "test.pnd"]).unwrap();
+        assert_eq!(args.verbosity(), Verbosity::Quiet);
+        assert_eq!(args.out_dir(Path::new("test.pnd")), Path::new("out"));
+        assert_eq!(
+            parse(&["list", "test.pnd"]).unwrap().verbosity(),
+            Verbosity::Normal
+        );
+    }
+
+    #[test]
+    fn bad_arguments() {
+        assert!(parse(&["build", "test.pnd", "-v", "-q"]).is_err());
+        assert!(parse(&["build"]).is_err());
+        assert!(parse(&["fmt", "--check"]).is_err());
+        assert!(parse(&["build", "test.pnd", "-D", "A"]).is_err());
+        assert!(parse(&["build", "test.pnd", "-j", "many"]).is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::project::tasks::ResType;
+use shared::ResType;
 
 #[derive(Debug, Clone, Copy)]
 pub enum PropConst {
@@ -7,6 +7,8 @@ pub enum PropConst {
     Fs,
     None,
     Auto,
+    Lower,
+    Upper,
     Error,
 }
 
@@ -15,20 +17,92 @@ pub enum PropValue {
     Int(i32),
     Int2(i32, i32),
     Int4(i32, i32, i32, i32),
+    Float(f64),
     Str(String),
     Const(PropConst),
     ValObj(String, Props),
+    /// Numbers computed when the task is resolved, one expression each.
+    Expr(Vec<Expr>),
     Empty,
 }
 
-pub type Props = Vec<(String, PropValue)>;
+#[derive(Debug, Clone, Copy)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// Arithmetic in a property value, spans point at names and operators for evaluation errors.
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Int(i32),
+    /// Integer written in hex, kept apart so formatting doesn't turn it into decimal.
+    Hex(i32),
+    Float(f64),
+    /// `w` or `h` of the source image, or a package define.
+    Var(String, Span),
+    Paren(Box<Expr>),
+    Binary(Box<Expr>, BinOp, Box<Expr>, Span),
+}
+
+/// Position of a token in the project file, 1-based.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub line: u32,
+    pub col: u32,
+}
 
+/// Properties in source order, each with the position of its key.
+pub type Props = Vec<(String, PropValue, Span)>;
+
+/// Nodes end with the position of their first token, blocks also with the position of their closing `}`.
 #[derive(Debug)]
 pub enum Node {
-    Package(String, Option<Props>, Vec<Node>),
-    Folder(String, Option<Props>, Vec<Node>),
-    Object(ResType, Option<String>, Option<Props>),
-    ObjectImport(ResType, Option<String>, String),
+    Package(String, Option<Props>, Vec<Node>, Span, Span),
+    Folder(String, Option<Props>, Vec<Node>, Span, Span),
+    Object(ResType, Option<String>, Option<Props>, Span),
+    ObjectImport(ResType, Option<String>, String, Span),
+    Include(String, Span),
+    /// Named props declared at package level, pulled into other props with `use`.
+    Preset(String, Props, Span),
+    /// Package level value for `${NAME}` in strings.
+    Define(String, String, Span),
+    /// Items that only count when building with the named profile.
+    Profile(String, Vec<Node>, Span, Span),
+}
+
+impl Node {
+    /// Position of the first token of the node.
+    pub fn span(&self) -> Span {
+        match self {
+            Node::Package(.., start, _)
+            | Node::Folder(.., start, _)
+            | Node::Profile(.., start, _) => *start,
+            Node::Object(.., start) | Node::ObjectImport(.., start) | Node::Include(_, start) => {
+                *start
+            }
+            Node::Preset(.., span) | Node::Define(.., span) => *span,
+        }
+    }
+}
+
+/// Plain decimal numbers and constants keep their own variants, anything else is left for evaluation.
+pub fn value_from_exprs(exprs: Vec<Expr>) -> PropValue {
+    match exprs.as_slice() {
+        &[Expr::Int(v)] => PropValue::Int(v),
+        &[Expr::Int(v1), Expr::Int(v2)] => PropValue::Int2(v1, v2),
+        &[Expr::Int(v1), Expr::Int(v2), Expr::Int(v3), Expr::Int(v4)] => {
+            PropValue::Int4(v1, v2, v3, v4)
+        }
+        &[Expr::Float(v)] => PropValue::Float(v),
+        [Expr::Var(name, _)] => match const_from_string(name.clone()) {
+            PropConst::Error => PropValue::Expr(exprs),
+            constant => PropValue::Const(constant),
+        },
+        _ => PropValue::Expr(exprs),
+    }
 }
 
 pub fn const_from_string(name: String) -> PropConst {
@@ -38,6 +112,8 @@ pub fn const_from_string(name: String) -> PropConst {
         "fs" => PropConst::Fs,
         "auto" => PropConst::Auto,
         "none" => PropConst::None,
+        "lower" => PropConst::Lower,
+        "upper" => PropConst::Upper,
         _ => PropConst::Error,
     }
 }
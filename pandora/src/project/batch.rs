@@ -0,0 +1,292 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail};
+
+/// Name with a single printf-style `%d` or `%0Nd` placeholder.
+pub struct NumberPattern {
+    prefix: String,
+    width: usize,
+    suffix: String,
+}
+
+impl NumberPattern {
+    pub fn parse(file_name: &str) -> anyhow::Result<NumberPattern> {
+        let error = || anyhow!("Expected one %d or %0Nd placeholder in \"{}\"", file_name);
+        let (prefix, rest) = file_name.split_once('%').ok_or_else(error)?;
+        let (spec, suffix) = rest.split_once('d').ok_or_else(error)?;
+        let width = match spec {
+            "" => 0,
+            _ if spec.starts_with('0') && spec.chars().all(|c| c.is_ascii_digit()) => {
+                spec.parse()?
+            }
+            _ => return Err(error()),
+        };
+        if suffix.contains('%') {
+            return Err(error());
+        }
+        Ok(NumberPattern {
+            prefix: prefix.to_string(),
+            width,
+            suffix: suffix.to_string(),
+        })
+    }
+
+    /// Plain number appended to `prefix`, like `%d` at the end of a pattern.
+    pub fn numbered(prefix: &str) -> NumberPattern {
+        NumberPattern {
+            prefix: prefix.to_string(),
+            width: 0,
+            suffix: String::new(),
+        }
+    }
+
+    pub fn format(&self, number: u32) -> String {
+        format!(
+            "{}{:0width$}{}",
+            self.prefix,
+            number,
+            self.suffix,
+            width = self.width
+        )
+    }
+
+    /// Number in `file_name` if the pattern would produce exactly that name.
+    fn number(&self, file_name: &str) -> Option<u32> {
+        let digits = file_name
+            .strip_prefix(&self.prefix)?
+            .strip_suffix(&self.suffix)?;
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let number = digits.parse().ok()?;
+        (self.format(number) == file_name).then_some(number)
+    }
+}
+
+/// Numbers of a `batch(...)` source to use. The frame files are only looked up when the task is built,
+/// so checking a project doesn't depend on them existing yet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchRange {
+    /// First number, the lowest one found on disk without it.
+    pub start: Option<u32>,
+    /// Last number, the highest one found on disk without it.
+    pub end: Option<u32>,
+    pub step: u32,
+}
+
+impl BatchRange {
+    /// Checks the pattern and the range without touching the file system.
+    pub fn new(
+        pattern: &Path,
+        start: Option<i32>,
+        end: Option<i32>,
+        step: i32,
+    ) -> anyhow::Result<BatchRange> {
+        number_pattern(pattern)?;
+        if step <= 0 {
+            bail!("Batch step must be positive, got {}", step);
+        }
+        for (key, value) in [("start", start), ("end", end)] {
+            if value.is_some_and(|value| value < 0) {
+                bail!("Batch {} can't be negative", key);
+            }
+        }
+        if let (Some(start), Some(end)) = (start, end)
+            && start > end
+        {
+            bail!("Batch start {} is after end {}", start, end);
+        }
+        Ok(BatchRange {
+            start: start.map(|start| start as u32),
+            end: end.map(|end| end as u32),
+            step: step as u32,
+        })
+    }
+}
+
+fn number_pattern(pattern: &Path) -> anyhow::Result<NumberPattern> {
+    let file_name = pattern
+        .file_name()
+        .ok_or_else(|| anyhow!("Batch pattern \"{}\" has no file name", pattern.display()))?;
+    NumberPattern::parse(&file_name.to_string_lossy())
+}
+
+/// Expands a numbered pattern like `menu/letter%04d.png` into the existing files of the sequence, in order.
+/// A missing number inside the range is an error, so a lost frame doesn't silently shift the animation.
+pub fn expand_batch(pattern: &Path, range: &BatchRange) -> anyhow::Result<Vec<PathBuf>> {
+    let number_pattern = number_pattern(pattern)?;
+    let dir = match pattern.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let mut numbers = HashSet::new();
+    for entry in
+        fs::read_dir(dir).map_err(|err| anyhow!("Can't read \"{}\": {}", dir.display(), err))?
+    {
+        let entry = entry?;
+        if entry.path().is_file()
+            && let Some(number) = number_pattern.number(&entry.file_name().to_string_lossy())
+        {
+            numbers.insert(number);
+        }
+    }
+    if numbers.is_empty() {
+        bail!("Pattern \"{}\" doesn't match any file", pattern.display());
+    }
+
+    let start = range
+        .start
+        .unwrap_or_else(|| *numbers.iter().min().unwrap());
+    let end = range.end.unwrap_or_else(|| *numbers.iter().max().unwrap());
+    if start > end {
+        bail!("Batch start {} is after end {}", start, end);
+    }
+
+    let mut result = Vec::new();
+    for number in (start..=end).step_by(range.step as usize) {
+        let name = number_pattern.format(number);
+        if !numbers.contains(&number) {
+            bail!("Sequence \"{}\" is missing \"{}\"", pattern.display(), name);
+        }
+        result.push(pattern.with_file_name(name));
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Empty directory under the system temp dir, unique per test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("pandora-batch-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn touch(dir: &Path, names: &[&str]) {
+        for name in names {
+            fs::write(dir.join(name), []).unwrap();
+        }
+    }
+
+    fn file_names(paths: &[PathBuf]) -> Vec<String> {
+        paths
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    fn range(start: Option<i32>, end: Option<i32>, step: i32) -> BatchRange {
+        BatchRange::new(Path::new("walk_%d.png"), start, end, step).unwrap()
+    }
+
+    #[test]
+    fn parse_pattern() {
+        let pattern = NumberPattern::parse("walk_%d.png").unwrap();
+        assert_eq!(pattern.format(7), "walk_7.png");
+        assert_eq!(pattern.format(12), "walk_12.png");
+
+        let pattern = NumberPattern::parse("letter%04d").unwrap();
+        assert_eq!(pattern.format(7), "letter0007");
+        assert_eq!(pattern.format(12345), "letter12345");
+
+        assert_eq!(NumberPattern::numbered("tile_").format(3), "tile_3");
+    }
+
+    #[test]
+    fn parse_bad_pattern() {
+        for pattern in [
+            "walk.png",
+            "walk_%.png",
+            "walk_%4d.png",
+            "walk_%x.png",
+            "%d_%d",
+        ] {
+            assert!(NumberPattern::parse(pattern).is_err(), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn match_number() {
+        let pattern = NumberPattern::parse("walk_%02d.png").unwrap();
+        assert_eq!(pattern.number("walk_07.png"), Some(7));
+        assert_eq!(pattern.number("walk_123.png"), Some(123));
+        assert_eq!(pattern.number("walk_7.png"), None);
+        assert_eq!(pattern.number("walk_007.png"), None);
+        assert_eq!(pattern.number("walk_.png"), None);
+        assert_eq!(pattern.number("walk_0a.png"), None);
+        assert_eq!(pattern.number("run_07.png"), None);
+    }
+
+    #[test]
+    fn bad_range() {
+        let pattern = Path::new("walk_%d.png");
+        assert!(BatchRange::new(pattern, None, None, 0).is_err());
+        assert!(BatchRange::new(pattern, Some(-1), None, 1).is_err());
+        assert!(BatchRange::new(pattern, None, Some(-1), 1).is_err());
+        assert!(BatchRange::new(pattern, Some(3), Some(2), 1).is_err());
+        assert!(BatchRange::new(Path::new("walk.png"), None, None, 1).is_err());
+    }
+
+    #[test]
+    fn expand_whole_sequence() {
+        let dir = temp_dir("whole");
+        touch(
+            &dir,
+            &[
+                "walk_3.png",
+                "walk_1.png",
+                "walk_2.png",
+                "walk_x.png",
+                "run_1.png",
+            ],
+        );
+        let frames = expand_batch(&dir.join("walk_%d.png"), &range(None, None, 1)).unwrap();
+        assert_eq!(
+            file_names(&frames),
+            ["walk_1.png", "walk_2.png", "walk_3.png"]
+        );
+        assert!(
+            frames
+                .iter()
+                .all(|frame| frame.parent() == Some(dir.as_path()))
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn expand_range_and_step() {
+        let dir = temp_dir("step");
+        touch(
+            &dir,
+            &[
+                "f00.png", "f01.png", "f02.png", "f03.png", "f04.png", "f05.png",
+            ],
+        );
+        let pattern = dir.join("f%02d.png");
+        let frames = expand_batch(&pattern, &range(Some(1), Some(5), 2)).unwrap();
+        assert_eq!(file_names(&frames), ["f01.png", "f03.png", "f05.png"]);
+        let frames = expand_batch(&pattern, &range(Some(4), None, 1)).unwrap();
+        assert_eq!(file_names(&frames), ["f04.png", "f05.png"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn expand_missing_frames() {
+        let dir = temp_dir("missing");
+        touch(&dir, &["walk_1.png", "walk_3.png"]);
+        let pattern = dir.join("walk_%d.png");
+        assert!(expand_batch(&pattern, &range(None, None, 1)).is_err());
+        assert!(expand_batch(&pattern, &range(None, None, 2)).is_ok());
+        assert!(expand_batch(&dir.join("run_%d.png"), &range(None, None, 1)).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,137 @@
+use std::{collections::HashMap, env};
+
+use anyhow::{anyhow, bail};
+
+use crate::project::ast::PropValue;
+
+/// Values for `${NAME}` in strings. Command line `-D` values win over package `define`s,
+/// which win over environment variables.
+#[derive(Debug, Clone, Default)]
+pub struct Defines {
+    overrides: HashMap<String, String>,
+    defined: HashMap<String, String>,
+}
+
+impl Defines {
+    pub fn new(overrides: HashMap<String, String>) -> Defines {
+        Defines {
+            overrides,
+            defined: HashMap::new(),
+        }
+    }
+
+    /// Adds a `define`, its value may refer to earlier defines.
+    /// A define overridden with `-D` is skipped, its value is never used.
+    pub fn define(&mut self, name: &str, value: &str) -> anyhow::Result<()> {
+        if self.overrides.contains_key(name) {
+            return Ok(());
+        }
+        let value = self.expand(value)?;
+        self.defined.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.overrides
+            .get(name)
+            .or_else(|| self.defined.get(name))
+            .cloned()
+            .or_else(|| env::var(name).ok())
+    }
+
+    /// Replaces every `${NAME}` in `text`, `$$` stands for a literal `$`.
+    pub fn expand(&self, text: &str) -> anyhow::Result<String> {
+        let mut result = String::new();
+        let mut rest = text;
+        while let Some(pos) = rest.find('$') {
+            result.push_str(&rest[..pos]);
+            rest = &rest[pos + 1..];
+            if let Some(after) = rest.strip_prefix('$') {
+                result.push('$');
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix('{') {
+                let (name, after) = after
+                    .split_once('}')
+                    .ok_or_else(|| anyhow!("Unterminated \"${{\" in \"{}\"", text))?;
+                if name.is_empty() {
+                    bail!("Empty variable name in \"{}\"", text);
+                }
+                let value = self
+                    .get(name)
+                    .ok_or_else(|| anyhow!("Undefined variable \"{}\" in \"{}\"", name, text))?;
+                result.push_str(&value);
+                rest = after;
+            } else {
+                result.push('$');
+            }
+        }
+        result.push_str(rest);
+        Ok(result)
+    }
+
+    /// Expands the strings of a property value, including the ones nested in `batch(...)` and `sheet(...)`.
+    pub fn expand_value(&self, value: &PropValue) -> anyhow::Result<PropValue> {
+        match value {
+            PropValue::Str(text) => Ok(PropValue::Str(self.expand(text)?)),
+            PropValue::ValObj(name, props) => {
+                let mut expanded = Vec::with_capacity(props.len());
+                for (key, value, span) in props {
+                    expanded.push((key.clone(), self.expand_value(value)?, *span));
+                }
+                Ok(PropValue::ValObj(name.clone(), expanded))
+            }
+            _ => Ok(value.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overrides(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn precedence() {
+        let path = env::var("PATH").unwrap();
+        let mut defines = Defines::new(overrides(&[("LEVEL", "cli")]));
+        assert_eq!(defines.get("PATH"), Some(path));
+
+        defines.define("PATH", "defined").unwrap();
+        defines.define("LEVEL", "defined").unwrap();
+        assert_eq!(defines.get("PATH").as_deref(), Some("defined"));
+        assert_eq!(defines.get("LEVEL").as_deref(), Some("cli"));
+    }
+
+    #[test]
+    fn overridden_define_is_not_expanded() {
+        let mut defines = Defines::new(overrides(&[("DIR", "assets")]));
+        defines.define("DIR", "${PANDORA_TEST_UNDEFINED}").unwrap();
+        assert_eq!(defines.get("DIR").as_deref(), Some("assets"));
+
+        let mut defines = Defines::default();
+        assert!(defines.define("DIR", "${PANDORA_TEST_UNDEFINED}").is_err());
+    }
+
+    #[test]
+    fn expand() {
+        let mut defines = Defines::new(overrides(&[("ROOT", "../assets")]));
+        defines.define("UI", "${ROOT}/ui").unwrap();
+        assert_eq!(
+            defines.expand("${UI}/logo.png").unwrap(),
+            "../assets/ui/logo.png"
+        );
+        assert_eq!(
+            defines.expand("$$ROOT $5 ${ROOT}").unwrap(),
+            "$ROOT $5 ../assets"
+        );
+        assert!(defines.expand("${ROOT").is_err());
+        assert!(defines.expand("${}").is_err());
+        assert!(defines.expand("${PANDORA_TEST_UNDEFINED}").is_err());
+    }
+}
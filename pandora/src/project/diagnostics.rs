@@ -0,0 +1,167 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// Problem in a project file, rendered rustc-style with the offending line and a caret under the column.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub file: PathBuf,
+    pub line: u32,
+    pub col: u32,
+    pub severity: Severity,
+    pub message: String,
+    pub help: Option<String>,
+    source_line: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(
+        file: &Path,
+        source: &str,
+        line: u32,
+        col: u32,
+        severity: Severity,
+        message: String,
+    ) -> Diagnostic {
+        let source_line = source
+            .lines()
+            .nth((line as usize).saturating_sub(1))
+            .map(|text| text.to_string());
+        Diagnostic {
+            file: file.to_path_buf(),
+            line,
+            col,
+            severity,
+            message,
+            help: None,
+            source_line,
+        }
+    }
+
+    pub fn with_help(mut self, help: String) -> Diagnostic {
+        self.help = Some(help);
+        self
+    }
+
+    pub fn error(file: &Path, source: &str, line: u32, col: u32, message: String) -> Diagnostic {
+        Diagnostic::new(file, source, line, col, Severity::Error, message)
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}: {}", self.severity, self.message)?;
+        let gutter = " ".repeat(self.line.to_string().len());
+        write!(
+            f,
+            "{}--> {}:{}:{}",
+            gutter,
+            self.file.display(),
+            self.line,
+            self.col
+        )?;
+
+        if let Some(text) = &self.source_line {
+            // Keep tabs in the caret line so it lines up with the source in any terminal.
+            let padding: String = text
+                .chars()
+                .take((self.col as usize).saturating_sub(1))
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            writeln!(f)?;
+            writeln!(f, "{} |", gutter)?;
+            writeln!(f, "{} | {}", self.line, text)?;
+            write!(f, "{} | {}^", gutter, padding)?;
+        }
+        if let Some(help) = &self.help {
+            writeln!(f)?;
+            write!(f, "{} = help: {}", gutter, help)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for Diagnostic {}
+
+/// Several diagnostics from the same pass, reported together so one run shows every problem.
+#[derive(Debug)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, diagnostic) in self.0.iter().enumerate() {
+            if index > 0 {
+                write!(f, "\n\n")?;
+            }
+            write!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for Diagnostics {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caret_under_column() {
+        let source = "package p {\n\ttex a (from \"a.png\", dither fast)\n}\n";
+        let diagnostic = Diagnostic::error(
+            Path::new("test.pnd"),
+            source,
+            2,
+            30,
+            String::from("unknown dithering `fast`"),
+        )
+        .with_help(String::from("expected one of none, fs, ord4, ord8"));
+        assert_eq!(
+            diagnostic.to_string(),
+            "error: unknown dithering `fast`\n \
+             --> test.pnd:2:30\n  \
+             |\n\
+             2 | \ttex a (from \"a.png\", dither fast)\n  \
+             | \t                            ^\n  \
+             = help: expected one of none, fs, ord4, ord8"
+        );
+    }
+
+    #[test]
+    fn several_without_source_line() {
+        let source = "package p {}\n";
+        let diagnostics = Diagnostics(vec![
+            Diagnostic::new(
+                Path::new("test.pnd"),
+                source,
+                12,
+                1,
+                Severity::Warning,
+                String::from("past the end"),
+            ),
+            Diagnostic::error(Path::new("test.pnd"), source, 1, 1, String::from("first")),
+        ]);
+        assert_eq!(
+            diagnostics.to_string(),
+            "warning: past the end\n  --> test.pnd:12:1\n\n\
+             error: first\n --> test.pnd:1:1\n  |\n1 | package p {}\n  | ^"
+        );
+    }
+}
@@ -0,0 +1,358 @@
+use crate::project::{
+    ast::{BinOp, Expr, PropValue, Span},
+    defines::Defines,
+};
+
+/// Problem found while evaluating, at the name or operator that caused it or, without a span,
+/// at the property the expressions belong to.
+pub struct ExprError {
+    pub span: Option<Span>,
+    pub message: String,
+}
+
+impl ExprError {
+    fn at(span: Span, message: String) -> ExprError {
+        ExprError {
+            span: Some(span),
+            message,
+        }
+    }
+}
+
+/// What the names in expressions refer to.
+pub struct Scope<'a> {
+    pub defines: &'a Defines,
+    /// Width and height of the source image, `None` where there is no image yet.
+    pub size: Option<(u32, u32)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+/// Expression refers to the width or height of the source image.
+pub fn mentions_size(expr: &Expr) -> bool {
+    match expr {
+        Expr::Var(name, _) => name == "w" || name == "h",
+        Expr::Paren(inner) => mentions_size(inner),
+        Expr::Binary(left, _, right, _) => mentions_size(left) || mentions_size(right),
+        Expr::Int(_) | Expr::Hex(_) | Expr::Float(_) => false,
+    }
+}
+
+/// Checks the defines `exprs` mention are numbers and returns their names. `w` and `h` are left for
+/// when the source image is read.
+pub fn check_defines<'a>(exprs: &'a [Expr], scope: &Scope) -> Result<Vec<&'a str>, ExprError> {
+    fn visit<'a>(expr: &'a Expr, scope: &Scope, names: &mut Vec<&'a str>) -> Result<(), ExprError> {
+        match expr {
+            Expr::Var(name, _) if name == "w" || name == "h" => {}
+            Expr::Var(name, span) => {
+                variable(name, *span, scope)?;
+                names.push(name);
+            }
+            Expr::Paren(inner) => visit(inner, scope, names)?,
+            Expr::Binary(left, _, right, _) => {
+                visit(left, scope, names)?;
+                visit(right, scope, names)?;
+            }
+            Expr::Int(_) | Expr::Hex(_) | Expr::Float(_) => {}
+        }
+        Ok(())
+    }
+
+    let mut names = Vec::new();
+    for expr in exprs {
+        visit(expr, scope, &mut names)?;
+    }
+    Ok(names)
+}
+
+/// Evaluates one number per expression. Whole numbers become `Int`, `Int2` or `Int4`, a single fraction `Float`.
+/// With `fractions` integers are turned into fractions first, so `35 / 4` is `8.75` rather than `8`.
+pub fn evaluate(exprs: &[Expr], scope: &Scope, fractions: bool) -> Result<PropValue, ExprError> {
+    let numbers = exprs
+        .iter()
+        .map(|expr| number(expr, scope, fractions))
+        .collect::<Result<Vec<Number>, ExprError>>()?;
+    if let [Number::Float(v)] = numbers.as_slice() {
+        return Ok(PropValue::Float(*v));
+    }
+
+    let mut ints = Vec::with_capacity(numbers.len());
+    for number in &numbers {
+        let &Number::Int(v) = number else {
+            return Err(ExprError {
+                span: None,
+                message: String::from(
+                    "expected whole numbers, only a single number can have a fraction",
+                ),
+            });
+        };
+        ints.push(i32::try_from(v).map_err(|_| ExprError {
+            span: None,
+            message: format!("{} is out of range", v),
+        })?);
+    }
+    match *ints.as_slice() {
+        [v] => Ok(PropValue::Int(v)),
+        [v1, v2] => Ok(PropValue::Int2(v1, v2)),
+        [v1, v2, v3, v4] => Ok(PropValue::Int4(v1, v2, v3, v4)),
+        _ => Err(ExprError {
+            span: None,
+            message: format!("expected 1, 2 or 4 numbers, got {}", ints.len()),
+        }),
+    }
+}
+
+fn number(expr: &Expr, scope: &Scope, fractions: bool) -> Result<Number, ExprError> {
+    let number = match expr {
+        &Expr::Int(v) | &Expr::Hex(v) => Number::Int(v.into()),
+        &Expr::Float(v) => Number::Float(v),
+        Expr::Var(name, span) => variable(name, *span, scope)?,
+        Expr::Paren(inner) => number(inner, scope, fractions)?,
+        Expr::Binary(left, op, right, span) => {
+            let left = number(left, scope, fractions)?;
+            let right = number(right, scope, fractions)?;
+            return binary(left, *op, right, *span);
+        }
+    };
+    Ok(match number {
+        Number::Int(v) if fractions => Number::Float(v as f64),
+        _ => number,
+    })
+}
+
+fn variable(name: &str, span: Span, scope: &Scope) -> Result<Number, ExprError> {
+    if name == "w" || name == "h" {
+        let (width, height) = scope.size.ok_or_else(|| {
+            ExprError::at(
+                span,
+                format!(
+                    "`{}` can't be used here, the source image isn't known yet",
+                    name
+                ),
+            )
+        })?;
+        return Ok(Number::Int(if name == "w" { width } else { height }.into()));
+    }
+
+    let value = scope
+        .defines
+        .get(name)
+        .ok_or_else(|| ExprError::at(span, format!("undefined variable `{}`", name)))?;
+    let value = value.trim();
+    if let Ok(v) = value.parse() {
+        Ok(Number::Int(v))
+    } else if let Ok(v) = value.parse() {
+        Ok(Number::Float(v))
+    } else {
+        Err(ExprError::at(
+            span,
+            format!("`{}` is \"{}\", not a number", name, value),
+        ))
+    }
+}
+
+fn binary(left: Number, op: BinOp, right: Number, span: Span) -> Result<Number, ExprError> {
+    if let (Number::Int(v1), Number::Int(v2)) = (left, right) {
+        if matches!(op, BinOp::Div) && v2 == 0 {
+            return Err(ExprError::at(span, String::from("division by zero")));
+        }
+        let result = match op {
+            BinOp::Add => v1.checked_add(v2),
+            BinOp::Sub => v1.checked_sub(v2),
+            BinOp::Mul => v1.checked_mul(v2),
+            BinOp::Div => v1.checked_div(v2),
+        };
+        return result
+            .map(Number::Int)
+            .ok_or_else(|| ExprError::at(span, String::from("result is out of range")));
+    }
+
+    let as_float = |number| match number {
+        Number::Int(v) => v as f64,
+        Number::Float(v) => v,
+    };
+    let (v1, v2) = (as_float(left), as_float(right));
+    if matches!(op, BinOp::Div) && v2 == 0.0 {
+        return Err(ExprError::at(span, String::from("division by zero")));
+    }
+    let result = match op {
+        BinOp::Add => v1 + v2,
+        BinOp::Sub => v1 - v2,
+        BinOp::Mul => v1 * v2,
+        BinOp::Div => v1 / v2,
+    };
+    if !result.is_finite() {
+        return Err(ExprError::at(span, String::from("result is out of range")));
+    }
+    Ok(Number::Float(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, path::Path};
+
+    use super::*;
+    use crate::project::{ast::Node, parse_source};
+
+    /// Expressions of `value` as written after a property key.
+    fn exprs(value: &str) -> Vec<Expr> {
+        let source = format!("package p (origin {}) {{ tex a }}", value);
+        let Ok(Node::Package(_, Some(props), ..)) = parse_source(Path::new("test.pnd"), &source)
+        else {
+            panic!("can't parse {}", value);
+        };
+        match &props[0].1 {
+            PropValue::Expr(exprs) => exprs.clone(),
+            value => panic!("{} is {:?}, not an expression", source, value),
+        }
+    }
+
+    fn defines() -> Defines {
+        let mut defines = Defines::default();
+        defines.define("HALF", "2").unwrap();
+        defines.define("SCALE", " 1.5 ").unwrap();
+        defines.define("NAME", "coin").unwrap();
+        defines
+    }
+
+    fn eval(value: &str, size: Option<(u32, u32)>, fractions: bool) -> Result<PropValue, String> {
+        let defines = defines();
+        let scope = Scope {
+            defines: &defines,
+            size,
+        };
+        evaluate(&exprs(value), &scope, fractions).map_err(|err| err.message)
+    }
+
+    fn int(value: &str) -> i32 {
+        match eval(value, Some((64, 16)), false) {
+            Ok(PropValue::Int(v)) => v,
+            result => panic!("{} is {:?}", value, result),
+        }
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(int("1 + 2 * 3"), 7);
+        assert_eq!(int("(1 + 2) * 3"), 9);
+        assert_eq!(int("10 - 4 - 3"), 3);
+        assert_eq!(int("35 / 4"), 8);
+        assert_eq!(int("-7 / 2"), -3);
+        assert_eq!(int("0x10 + 1"), 17);
+        assert_eq!(int("w / HALF"), 32);
+        assert_eq!(int("w / 4 - h"), 0);
+    }
+
+    #[test]
+    fn several_numbers() {
+        let size = Some((64, 16));
+        assert!(matches!(
+            eval("(w / 4) / HALF h - 1", size, false),
+            Ok(PropValue::Int2(8, 15))
+        ));
+        assert!(matches!(
+            eval("1 2 + 1 HALF w", size, false),
+            Ok(PropValue::Int4(1, 3, 2, 64))
+        ));
+        assert_eq!(
+            eval("1 2 3", size, false).unwrap_err(),
+            "expected 1, 2 or 4 numbers, got 3"
+        );
+        assert_eq!(
+            eval("1 SCALE", size, false).unwrap_err(),
+            "expected whole numbers, only a single number can have a fraction"
+        );
+    }
+
+    #[test]
+    fn fractions() {
+        assert!(matches!(eval("35 / 4", None, true), Ok(PropValue::Float(v)) if v == 8.75));
+        assert!(matches!(eval("SCALE * 2", None, false), Ok(PropValue::Float(v)) if v == 3.0));
+        assert!(matches!(eval("1 + 1", None, true), Ok(PropValue::Float(v)) if v == 2.0));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            eval("1 / (HALF - 2)", None, false).unwrap_err(),
+            "division by zero"
+        );
+        assert_eq!(
+            eval("1.5 / (1 - 1)", None, false).unwrap_err(),
+            "division by zero"
+        );
+        assert_eq!(
+            eval("2147483647 + 1", None, false).unwrap_err(),
+            "2147483648 is out of range"
+        );
+        assert_eq!(
+            eval("2147483647 * 2147483647 * 4", None, false).unwrap_err(),
+            "result is out of range"
+        );
+        assert_eq!(
+            eval("OTHER + 1", None, false).unwrap_err(),
+            "undefined variable `OTHER`"
+        );
+        assert_eq!(
+            eval("NAME + 1", None, false).unwrap_err(),
+            "`NAME` is \"coin\", not a number"
+        );
+        assert_eq!(
+            eval("w / 2", None, false).unwrap_err(),
+            "`w` can't be used here, the source image isn't known yet"
+        );
+    }
+
+    #[test]
+    fn error_position() {
+        let defines = defines();
+        let scope = Scope {
+            defines: &defines,
+            size: None,
+        };
+        // `package p (origin ` is 18 characters.
+        let err = evaluate(&exprs("1 + OTHER"), &scope, false).err().unwrap();
+        assert_eq!(err.span, Some(Span { line: 1, col: 23 }));
+        let err = evaluate(&exprs("1 / 0"), &scope, false).err().unwrap();
+        assert_eq!(err.span, Some(Span { line: 1, col: 21 }));
+    }
+
+    #[test]
+    fn defines_next_to_size() {
+        let defines = defines();
+        let scope = Scope {
+            defines: &defines,
+            size: None,
+        };
+        let exprs = exprs("w / HALF h * SCALE");
+        assert!(exprs.iter().any(mentions_size));
+        assert_eq!(
+            check_defines(&exprs, &scope).ok().unwrap(),
+            ["HALF", "SCALE"]
+        );
+        let err = check_defines(&self::exprs("w / OTHER"), &scope)
+            .err()
+            .unwrap();
+        assert_eq!(err.message, "undefined variable `OTHER`");
+        assert!(!self::exprs("HALF + 1").iter().any(mentions_size));
+    }
+
+    #[test]
+    fn overridden_define() {
+        let overrides = HashMap::from([(String::from("HALF"), String::from("4"))]);
+        let mut defines = Defines::new(overrides);
+        defines.define("HALF", "2").unwrap();
+        let scope = Scope {
+            defines: &defines,
+            size: Some((64, 16)),
+        };
+        assert!(matches!(
+            evaluate(&exprs("w / HALF"), &scope, false),
+            Ok(PropValue::Int(16))
+        ));
+    }
+}
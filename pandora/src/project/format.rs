@@ -0,0 +1,333 @@
+use std::collections::VecDeque;
+
+use crate::project::{
+    ast::{BinOp, Expr, Node, PropValue, Props},
+    lexer::Comment,
+    validate::class_name,
+};
+
+const INDENT: &str = "    ";
+
+/// Params that would make a line longer than this get a line each.
+const MAX_WIDTH: usize = 100;
+
+/// Prints a syntax tree back as project source in canonical form: four space indents, lowercase keywords,
+/// params on one line while they fit, a line each otherwise. Comments stay next to the node or param they
+/// precede or follow, single blank lines between items are kept.
+pub fn format_tree(root: &Node, comments: Vec<Comment>, source: &str) -> String {
+    let mut printer = Printer {
+        lines: source.lines().collect(),
+        comments: comments.into(),
+        out: String::new(),
+        depth: 0,
+        block_start: true,
+    };
+    printer.node(root);
+    printer.comments_before(u32::MAX);
+    printer.out
+}
+
+struct Printer<'a> {
+    lines: Vec<&'a str>,
+    /// Comments not printed yet, in source order.
+    comments: VecDeque<Comment>,
+    out: String,
+    depth: usize,
+    /// Nothing printed in the current block yet, blank lines are dropped here.
+    block_start: bool,
+}
+
+impl Printer<'_> {
+    fn node(&mut self, node: &Node) {
+        match node {
+            Node::Package(name, props, items, start, end) => {
+                self.item_start(start.line);
+                self.block(
+                    format!("package {}", name),
+                    props,
+                    items,
+                    start.line,
+                    end.line,
+                );
+            }
+            Node::Folder(path, props, items, start, end) => {
+                self.item_start(start.line);
+                self.block(quote(path), props, items, start.line, end.line);
+            }
+            Node::Object(res_type, name, props, start) => {
+                self.item_start(start.line);
+                let mut head = class_name(*res_type).to_string();
+                if let Some(name) = name {
+                    head = format!("{} {}", head, name);
+                }
+                match props {
+                    Some(props) => self.with_params(&head, props, start.line, "", u32::MAX),
+                    None => self.line(&head, start.line),
+                }
+            }
+            Node::ObjectImport(res_type, name, path, start) => {
+                self.item_start(start.line);
+                let head = match name {
+                    Some(name) => format!("{} {}", class_name(*res_type), name),
+                    None => class_name(*res_type).to_string(),
+                };
+                self.line(&format!("{} import {}", head, quote(path)), start.line);
+            }
+            Node::Include(path, start) => {
+                self.item_start(start.line);
+                self.line(&format!("include {}", quote(path)), start.line);
+            }
+            Node::Preset(name, props, span) => {
+                self.item_start(span.line);
+                self.with_params(&format!("preset {}", name), props, span.line, "", u32::MAX);
+            }
+            Node::Define(name, value, span) => {
+                self.item_start(span.line);
+                self.line(&format!("define {} {}", name, quote(value)), span.line);
+            }
+            Node::Profile(name, items, start, end) => {
+                self.item_start(start.line);
+                self.block(
+                    format!("profile {}", name),
+                    &None,
+                    items,
+                    start.line,
+                    end.line,
+                );
+            }
+        }
+    }
+
+    fn block(
+        &mut self,
+        head: String,
+        props: &Option<Props>,
+        items: &[Node],
+        start_line: u32,
+        end_line: u32,
+    ) {
+        // Comments after the first item belong to it, even if it shares a line with the block start.
+        let head_limit = items.first().map_or(end_line, |item| item.span().line) - 1;
+        match props {
+            Some(props) => self.with_params(&head, props, start_line, " {", head_limit),
+            None => self.line(&format!("{} {{", head), start_line.min(head_limit)),
+        }
+        self.depth += 1;
+        self.block_start = true;
+        for item in items {
+            self.node(item);
+        }
+        self.comments_before(end_line);
+        self.depth -= 1;
+        self.line("}", end_line);
+    }
+
+    /// `head (params)suffix` on one line, or one param per line when they don't fit or comments sit between them.
+    /// Comments written after source lines past `limit` are left for the following node.
+    fn with_params(&mut self, head: &str, props: &Props, head_line: u32, suffix: &str, limit: u32) {
+        let params: Vec<String> = props
+            .iter()
+            .map(|(key, value, _)| format_param(key, value))
+            .collect();
+        let last_line = props.last().map_or(head_line, |(_, _, span)| span.line);
+        let inline = format!("{} ({}){}", head, params.join(", "), suffix);
+        let has_comments = self
+            .comments
+            .iter()
+            .any(|comment| comment.span.line >= head_line && comment.span.line < last_line);
+        if !has_comments && self.depth * INDENT.len() + inline.chars().count() <= MAX_WIDTH {
+            self.line(&inline, last_line.min(limit));
+            return;
+        }
+
+        // A comment after the first param goes with that param, even on the line of the head.
+        let first_line = props.first().map_or(head_line, |(_, _, span)| span.line);
+        let head_claim = if first_line > head_line { head_line } else { 0 };
+        self.line(&format!("{} (", head), head_claim.min(limit));
+        self.depth += 1;
+        for (index, (text, (_, _, span))) in params.iter().zip(props).enumerate() {
+            self.comments_before(span.line);
+            let separator = if index + 1 < params.len() { "," } else { "" };
+            self.line(&format!("{}{}", text, separator), span.line.min(limit));
+        }
+        self.depth -= 1;
+        self.line(&format!("){}", suffix), 0);
+    }
+
+    fn item_start(&mut self, line: u32) {
+        self.comments_before(line);
+        self.blank_line(line);
+    }
+
+    /// Prints the comments above `line` on lines of their own.
+    fn comments_before(&mut self, line: u32) {
+        while let Some(comment) = self.comments.front()
+            && comment.span.line < line
+        {
+            let comment = self.comments.pop_front().unwrap();
+            self.blank_line(comment.span.line);
+            self.line(&comment.text, comment.span.line);
+        }
+    }
+
+    /// Keeps a blank line from the source above `line`, unless it would open a block.
+    fn blank_line(&mut self, line: u32) {
+        let blank_above = line >= 2
+            && self
+                .lines
+                .get(line as usize - 2)
+                .is_some_and(|text| text.trim().is_empty());
+        if blank_above && !self.block_start && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+
+    /// Prints an indented line, followed by the comment written after the source on `source_line`.
+    fn line(&mut self, text: &str, source_line: u32) {
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+        self.out.push_str(text);
+        while let Some(comment) = self.comments.front()
+            && comment.trailing
+            && comment.span.line <= source_line
+        {
+            let comment = self.comments.pop_front().unwrap();
+            self.out.push(' ');
+            self.out.push_str(&comment.text);
+        }
+        self.out.push('\n');
+        self.block_start = false;
+    }
+}
+
+fn format_param(key: &str, value: &PropValue) -> String {
+    match (key, value) {
+        ("use", PropValue::Str(name)) => format!("use {}", name),
+        ("profile", PropValue::ValObj(name, props)) => {
+            format!("profile {} ({})", name, format_params(props))
+        }
+        (_, PropValue::Empty) => key.to_string(),
+        _ => format!("{} {}", key, format_value(value)),
+    }
+}
+
+/// Value as written in a project file.
+pub fn format_value(value: &PropValue) -> String {
+    match value {
+        PropValue::Int(v) => v.to_string(),
+        PropValue::Int2(v1, v2) => format!("{} {}", v1, v2),
+        PropValue::Int4(v1, v2, v3, v4) => format!("{} {} {} {}", v1, v2, v3, v4),
+        // Debug keeps the fraction of whole numbers, `2.0` must not turn into the integer `2`.
+        PropValue::Float(v) => format!("{:?}", v),
+        PropValue::Str(text) => quote(text),
+        PropValue::Const(constant) => format!("{:?}", constant).to_lowercase(),
+        PropValue::ValObj(name, props) => format!("{}({})", name, format_params(props)),
+        PropValue::Expr(exprs) => exprs
+            .iter()
+            .map(format_expr)
+            .collect::<Vec<String>>()
+            .join(" "),
+        PropValue::Empty => String::new(),
+    }
+}
+
+/// Parentheses are kept as written, so are the spaces around operators that tell `1 - 2` from `1 -2`.
+fn format_expr(expr: &Expr) -> String {
+    match expr {
+        Expr::Int(v) => v.to_string(),
+        &Expr::Hex(v) if v < 0 => format!("-0x{:x}", v.unsigned_abs()),
+        Expr::Hex(v) => format!("0x{:x}", v),
+        Expr::Float(v) => format!("{:?}", v),
+        Expr::Var(name, _) => name.clone(),
+        Expr::Paren(inner) => format!("({})", format_expr(inner)),
+        Expr::Binary(left, op, right, _) => {
+            let op = match op {
+                BinOp::Add => "+",
+                BinOp::Sub => "-",
+                BinOp::Mul => "*",
+                BinOp::Div => "/",
+            };
+            format!("{} {} {}", format_expr(left), op, format_expr(right))
+        }
+    }
+}
+
+fn format_params(props: &Props) -> String {
+    props
+        .iter()
+        .map(|(key, value, _)| format_param(key, value))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::project::parse_with_comments;
+
+    fn format(source: &str) -> String {
+        let (root, comments) = parse_with_comments(Path::new("test.pnd"), source).unwrap();
+        format_tree(&root, comments, source)
+    }
+
+    #[test]
+    fn idempotent() {
+        let source = "package p {\n\
+                      \t\"/ui\"   (from \"ui\",dither ord4){\n\
+                      tex logo(cols 16 , rows 2*2)\n\
+                      \n\
+                      \n\
+                      font main (from sheet(file \"main.png\", cols 16, rows 6), borders 1 2 3 4)\n\
+                      }\n\
+                      }\n";
+        let once = format(source);
+        assert_eq!(format(&once), once);
+    }
+
+    #[test]
+    fn comments_around_block_starts() {
+        let source = "# header\n\
+                      package p {  # after package\n\
+                      # before folder\n\
+                      \"/ui\" {  # after folder\n\
+                      # first in folder\n\
+                      tex logo\n\
+                      }\n\
+                      # before closing\n\
+                      }\n";
+        let expected = "# header\n\
+                        package p { # after package\n    \
+                        # before folder\n    \
+                        \"/ui\" { # after folder\n        \
+                        # first in folder\n        \
+                        tex logo\n    \
+                        }\n    \
+                        # before closing\n\
+                        }\n";
+        assert_eq!(format(source), expected);
+        assert_eq!(format(expected), expected);
+    }
+
+    #[test]
+    fn keeps_hex_numbers() {
+        let formatted = format("package p { tex logo (cols 0x10, rows -0x2, fallback_char 0xFF) }");
+        assert!(
+            formatted.contains("tex logo (cols 0x10, rows -0x2, fallback_char 0xff)"),
+            "{}",
+            formatted
+        );
+    }
+
+    #[test]
+    fn keeps_unknown_names() {
+        let formatted = format("package p { tex logo (dither foo) }");
+        assert!(formatted.contains("tex logo (dither foo)"), "{}", formatted);
+    }
+}
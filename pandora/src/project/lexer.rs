@@ -1,17 +1,35 @@
 use std::str::Chars;
 
-use crate::project::parser::Token;
+use crate::project::{ast::Span, parser::Token};
+
+/// Input the lexer can't turn into a token, reported at the position where the token starts.
+pub struct LexError {
+    pub line: u32,
+    pub col: u32,
+    pub message: String,
+}
+
+/// `#` comment, kept aside for the formatter since the parser never sees them.
+#[derive(Debug, Clone)]
+pub struct Comment {
+    pub span: Span,
+    /// Comment text starting with `#`, without the line break and trailing spaces.
+    pub text: String,
+    /// Comment follows a token on the same line instead of standing on its own.
+    pub trailing: bool,
+}
+
 pub struct Lexer<'a> {
     data: Chars<'a>,
     cur_char: char,
+    /// Character before `cur_char`, `'\0'` at the start.
+    prev_char: char,
     eof: bool,
     line: u32,
     col: u32,
-}
-
-enum NameVariant {
-    Name(String),
-    Keyword(Token),
+    /// Line of the last token returned, 0 before the first one.
+    token_line: u32,
+    comments: Vec<Comment>,
 }
 
 impl<'a> Lexer<'a> {
@@ -19,15 +37,18 @@ impl<'a> Lexer<'a> {
         let mut result = Lexer {
             data: source.chars(),
             cur_char: '\0',
+            prev_char: '\0',
             eof: false,
             line: 1,
             col: 1,
+            token_line: 0,
+            comments: Vec::new(),
         };
         result.forward();
         result
     }
 
-    pub fn next(&mut self) -> Option<(Token, u32, u32)> {
+    pub fn next(&mut self) -> Option<Result<(Token, u32, u32), LexError>> {
         self.skip_spaces();
         if self.eof {
             return None;
@@ -42,48 +63,84 @@ impl<'a> Lexer<'a> {
 
         let line = self.line;
         let col = self.col - 1;
+        let span = Span { line, col };
+        self.token_line = line;
 
-        match self.cur_char {
-            '0'..='9' => Some((Token::Int(self.read_int()), line, col)),
-            '"' => Some((Token::Str(self.read_str()), line, col)),
+        let error = |message: String| Some(Err(LexError { line, col, message }));
+
+        let token = match self.cur_char {
+            // `10 -4` are two numbers, `10 - 4` and `10-4` a subtraction.
+            c if c.is_ascii_digit() || (c == '-' && self.starts_negative_number()) => {
+                match self.read_number() {
+                    Ok(token) => token,
+                    Err(message) => return error(message),
+                }
+            }
+            '-' => {
+                self.forward();
+                Token::Minus(span)
+            }
+            '+' => {
+                self.forward();
+                Token::Plus(span)
+            }
+            '/' => {
+                self.forward();
+                Token::Slash(span)
+            }
+            '"' => match self.read_str() {
+                Some(value) => Token::Str((value, span)),
+                None => return error(String::from("unterminated string")),
+            },
             '(' => {
                 self.forward();
-                Some((Token::LParen, line, col))
+                Token::LParen
             }
             ')' => {
                 self.forward();
-                Some((Token::RParen, line, col))
+                Token::RParen
             }
             '{' => {
                 self.forward();
-                Some((Token::LBracket, line, col))
+                Token::LBracket
             }
             '}' => {
                 self.forward();
-                Some((Token::RBracket, line, col))
+                Token::RBracket(span)
             }
             ',' => {
                 self.forward();
-                Some((Token::Comma, line, col))
+                Token::Comma
             }
             '*' => {
                 self.forward();
-                Some((Token::Asterisk, line, col))
+                Token::Asterisk(span)
             }
             _ => {
                 if self.cur_char.is_ascii_alphabetic() {
-                    match self.read_name() {
-                        NameVariant::Keyword(kw) => Some((kw, line, col)),
-                        NameVariant::Name(name) => Some((Token::Name(name), line, col)),
-                    }
+                    self.read_name(span)
                 } else {
-                    None
+                    let unknown = self.cur_char;
+                    self.forward();
+                    return error(format!("unexpected character {:?}", unknown));
                 }
             }
-        }
+        };
+        Some(Ok((token, line, col)))
+    }
+
+    /// Line and column right after the last consumed character.
+    pub fn position(&self) -> (u32, u32) {
+        (self.line, self.col)
+    }
+
+    /// Comments skipped so far, in source order.
+    pub fn into_comments(self) -> Vec<Comment> {
+        self.comments
     }
 
     fn forward(&mut self) {
+        self.prev_char = self.cur_char;
         if let Some(next_char) = self.data.next() {
             self.cur_char = next_char;
             if next_char == '\n' {
@@ -104,22 +161,86 @@ impl<'a> Lexer<'a> {
     }
 
     fn skip_comments(&mut self) {
+        let span = Span {
+            line: self.line,
+            col: self.col - 1,
+        };
+        let mut text = String::new();
         while !self.eof && self.cur_char != '\n' {
+            text.push(self.cur_char);
             self.forward();
         }
         self.forward();
+        self.comments.push(Comment {
+            span,
+            text: text.trim_end().to_string(),
+            trailing: span.line == self.token_line,
+        });
     }
 
-    fn read_int(&mut self) -> i32 {
-        let mut result = String::new();
+    fn peek(&self) -> Option<char> {
+        self.data.clone().next()
+    }
+
+    /// `-` right before a digit, not right after something a subtraction could follow.
+    fn starts_negative_number(&self) -> bool {
+        let after_operand =
+            self.prev_char.is_ascii_alphanumeric() || matches!(self.prev_char, '_' | ')');
+        !after_operand && self.peek().is_some_and(|c| c.is_ascii_digit())
+    }
+
+    fn read_digits(&mut self, result: &mut String) {
         while !self.eof && self.cur_char.is_ascii_digit() {
             result.push(self.cur_char);
             self.forward();
         }
-        result.parse().unwrap_or(0)
     }
 
-    fn read_str(&mut self) -> String {
+    /// Signed decimal integer, `0x` hex integer or decimal fraction like `12.5`.
+    fn read_number(&mut self) -> Result<Token, String> {
+        let mut result = String::new();
+        if self.cur_char == '-' {
+            result.push('-');
+            self.forward();
+            if self.eof || !self.cur_char.is_ascii_digit() {
+                return Err(String::from("expected a number after '-'"));
+            }
+        }
+
+        if self.cur_char == '0' && matches!(self.peek(), Some('x' | 'X')) {
+            self.forward();
+            self.forward();
+            let mut digits = String::new();
+            while !self.eof && self.cur_char.is_ascii_alphanumeric() {
+                digits.push(self.cur_char);
+                self.forward();
+            }
+            if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(format!("invalid hex number \"0x{}\"", digits));
+            }
+            result.push_str(&digits);
+            return i32::from_str_radix(&result, 16)
+                .map(Token::Hex)
+                .map_err(|_| String::from("number is too large"));
+        }
+
+        self.read_digits(&mut result);
+        if self.cur_char == '.' && self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            result.push('.');
+            self.forward();
+            self.read_digits(&mut result);
+            return result
+                .parse()
+                .map(Token::Float)
+                .map_err(|_| format!("invalid number \"{}\"", result));
+        }
+        result
+            .parse()
+            .map(Token::Int)
+            .map_err(|_| String::from("number is too large"))
+    }
+
+    fn read_str(&mut self) -> Option<String> {
         self.forward();
         let mut escaping = false;
         let mut result = String::new();
@@ -130,32 +251,165 @@ impl<'a> Lexer<'a> {
                 escaping = false;
             } else {
                 match self.cur_char {
-                    '"' => break,
+                    '"' => {
+                        self.forward();
+                        return Some(result);
+                    }
                     '\\' => escaping = true,
                     _ => result.push(self.cur_char),
                 }
                 self.forward();
             }
         }
-        self.forward();
-        result
+        None
     }
 
-    fn read_name(&mut self) -> NameVariant {
+    /// Keyword or name, keywords are case-insensitive.
+    fn read_name(&mut self, span: Span) -> Token {
         let mut result = String::new();
         while !self.eof && (self.cur_char.is_ascii_alphanumeric() || self.cur_char == '_') {
             result.push(self.cur_char);
             self.forward();
         }
         match result.to_lowercase().as_str() {
-            "package" => NameVariant::Keyword(Token::KwPackage),
-            "tex" => NameVariant::Keyword(Token::KwTex),
-            "font" => NameVariant::Keyword(Token::KwFont),
-            "sprite" => NameVariant::Keyword(Token::KwSprite),
-            "import" => NameVariant::Keyword(Token::KwImport),
-            "intmap" => NameVariant::Keyword(Token::KwIntMap),
-            "extmap" => NameVariant::Keyword(Token::KwExtMap),
-            _ => NameVariant::Name(result),
+            "package" => Token::KwPackage(span),
+            "tex" => Token::KwTex(span),
+            "font" => Token::KwFont(span),
+            "sprite" => Token::KwSprite(span),
+            "import" => Token::KwImport,
+            "include" => Token::KwInclude(span),
+            "preset" => Token::KwPreset,
+            "use" => Token::KwUse,
+            "define" => Token::KwDefine,
+            "intmap" => Token::KwIntMap(span),
+            "extmap" => Token::KwExtMap(span),
+            "profile" => Token::KwProfile(span),
+            _ => Token::Name((result, span)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Numbers as their value, `-` as itself, other tokens the way syntax errors spell them.
+    fn tokens(source: &str) -> Vec<String> {
+        let mut lexer = Lexer::new(source);
+        let mut result = Vec::new();
+        while let Some(next) = lexer.next() {
+            let (token, ..) = next.unwrap_or_else(|err| panic!("{}", err.message));
+            result.push(match token {
+                Token::Int(v) | Token::Hex(v) => v.to_string(),
+                Token::Float(v) => v.to_string(),
+                Token::Minus(_) => String::from("-"),
+                Token::Name((name, _)) => name,
+                token => token.to_string(),
+            });
+        }
+        result
+    }
+
+    /// Line, column and message of the first error in `source`.
+    fn error(source: &str) -> (u32, u32, String) {
+        let mut lexer = Lexer::new(source);
+        while let Some(next) = lexer.next() {
+            if let Err(err) = next {
+                return (err.line, err.col, err.message);
+            }
+        }
+        panic!("no error in {:?}", source);
+    }
+
+    #[test]
+    fn minus() {
+        assert_eq!(tokens("10 -4"), ["10", "-4"]);
+        assert_eq!(tokens("10 - 4"), ["10", "-", "4"]);
+        assert_eq!(tokens("10-4"), ["10", "-", "4"]);
+        assert_eq!(tokens("-4 -0x10 -2.5"), ["-4", "-16", "-2.5"]);
+        assert_eq!(tokens("h-1"), ["h", "-", "1"]);
+        assert_eq!(tokens("h -1"), ["h", "-1"]);
+        assert_eq!(tokens("(w)-1"), ["'('", "w", "')'", "-", "1"]);
+        assert_eq!(tokens("(-1)"), ["'('", "-1", "')'"]);
+        assert_eq!(tokens("-w"), ["-", "w"]);
+    }
+
+    #[test]
+    fn minus_without_number() {
+        let mut lexer = Lexer::new("- ");
+        assert!(matches!(lexer.next(), Some(Ok((Token::Minus(_), 1, 1)))));
+        assert!(lexer.next().is_none());
+    }
+
+    #[test]
+    fn errors_at_their_position() {
+        assert_eq!(
+            error("package p {\n    tex a ; b\n}"),
+            (2, 11, String::from("unexpected character ';'"))
+        );
+        assert_eq!(
+            error("tex a (from \"a.png)\n"),
+            (1, 13, String::from("unterminated string"))
+        );
+        assert_eq!(
+            error("# comment\n  \"\\\""),
+            (2, 3, String::from("unterminated string"))
+        );
+        assert_eq!(
+            error("origin 0xfg"),
+            (1, 8, String::from("invalid hex number \"0xfg\""))
+        );
+    }
+
+    #[test]
+    fn errors_become_diagnostics() {
+        let err = crate::project::parse_source(
+            std::path::Path::new("test.pnd"),
+            "package p {\n    tex a (from \"a.png\", dither @)\n}\n",
+        )
+        .unwrap_err();
+        assert_eq!((err.line, err.col), (2, 33));
+        assert_eq!(err.message, "unexpected character '@'");
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(tokens("0 16 007"), ["0", "16", "7"]);
+        assert_eq!(tokens("0xFF00ff 0X10"), ["16711935", "16"]);
+        assert_eq!(tokens("12.5 0.25"), ["12.5", "0.25"]);
+        assert_eq!(
+            tokens("2147483647 -2147483648"),
+            ["2147483647", "-2147483648"]
+        );
+        assert_eq!(tokens("-0x80000000"), ["-2147483648"]);
+        assert!(matches!(
+            Lexer::new("0x10").next(),
+            Some(Ok((Token::Hex(16), 1, 1)))
+        ));
+        assert!(matches!(
+            Lexer::new("12.5").next(),
+            Some(Ok((Token::Float(v), 1, 1))) if v == 12.5
+        ));
+    }
+
+    #[test]
+    fn bad_numbers() {
+        for source in ["2147483648", "0x80000000", "-2147483649"] {
+            assert_eq!(
+                error(source),
+                (1, 1, String::from("number is too large")),
+                "{}",
+                source
+            );
         }
+        assert_eq!(
+            error("0x"),
+            (1, 1, String::from("invalid hex number \"0x\""))
+        );
+        // Without digits after it the dot isn't part of the number.
+        assert_eq!(
+            error("12."),
+            (1, 3, String::from("unexpected character '.'"))
+        );
     }
 }
@@ -1,37 +1,182 @@
-use std::{fs, path::Path};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Context, bail};
 
 use crate::project::{
     ast::Node,
-    lexer::Lexer,
+    diagnostics::{Diagnostic, Diagnostics, Severity},
+    format::format_tree,
+    lexer::{Comment, Lexer},
     parser::{Parser, ParserState},
-    tasks::{PackageTask, Task, generate_project, generate_task},
+    tasks::{PackageTask, generate_project},
+    validate::validate,
 };
 
 pub mod ast;
-mod lexer;
+pub mod batch;
+mod defines;
+pub mod diagnostics;
+mod expr;
+pub mod format;
+pub mod lexer;
 pub mod parser;
-mod tasks;
+pub mod tasks;
+pub mod validate;
+
+/// Parses and validates a project file, returns its syntax tree and text.
+/// Warnings are printed, errors are all returned together.
+fn parse_file<P: AsRef<Path>>(source_file: P) -> anyhow::Result<(Node, String)> {
+    let source_file = source_file.as_ref();
+    let source = read_source(source_file)?;
+    let root = parse_source(source_file, &source)?;
+
+    let (errors, warnings): (Vec<Diagnostic>, Vec<Diagnostic>) =
+        validate(&root, source_file, &source)
+            .into_iter()
+            .partition(|diagnostic| diagnostic.severity == Severity::Error);
+    for warning in warnings {
+        eprintln!("{}\n", warning);
+    }
+    if !errors.is_empty() {
+        return Err(Diagnostics(errors).into());
+    }
+    Ok((root, source))
+}
+
+/// Syntax tree of a project file, without validating the properties.
+pub fn ast_from_file<P: AsRef<Path>>(source_file: P) -> anyhow::Result<Node> {
+    let source_file = source_file.as_ref();
+    let source = read_source(source_file)?;
+    Ok(parse_source(source_file, &source)?)
+}
+
+fn read_source(source_file: &Path) -> anyhow::Result<String> {
+    fs::read_to_string(source_file).with_context(|| format!("Can't read {}", source_file.display()))
+}
+
+/// Source of a project file and the same file in canonical formatting.
+pub fn format_file<P: AsRef<Path>>(source_file: P) -> anyhow::Result<(String, String)> {
+    let source_file = source_file.as_ref();
+    let source = read_source(source_file)?;
+    let (root, comments) = parse_with_comments(source_file, &source)?;
+    let formatted = format_tree(&root, comments, &source);
+    if let Err(err) = parse_source(source_file, &formatted) {
+        bail!(
+            "Formatting {} produced invalid syntax:\n{}",
+            source_file.display(),
+            err
+        );
+    }
+    Ok((source, formatted))
+}
 
-fn parse_file<P: AsRef<Path>>(source_file: P) -> anyhow::Result<Node> {
-    let source = fs::read_to_string(source_file)?;
+/// Parse and validation problems of source text that may not be saved yet, and its syntax tree if it parses.
+pub fn check_source(source_file: &Path, source: &str) -> (Option<Node>, Vec<Diagnostic>) {
+    match parse_source(source_file, source) {
+        Ok(root) => {
+            let diagnostics = validate(&root, source_file, source);
+            (Some(root), diagnostics)
+        }
+        Err(diagnostic) => (None, vec![diagnostic]),
+    }
+}
+
+fn parse_source(source_file: &Path, source: &str) -> Result<Node, Diagnostic> {
+    parse_with_comments(source_file, source).map(|(root, _)| root)
+}
 
+fn parse_with_comments(
+    source_file: &Path,
+    source: &str,
+) -> Result<(Node, Vec<Comment>), Diagnostic> {
     let mut par = Parser::new(ParserState { line: 1, col: 1 });
-    let mut lex = Lexer::new(&source);
+    let mut lex = Lexer::new(source);
 
-    while let Some((token, line, col)) = lex.next() {
+    while let Some(next) = lex.next() {
+        let (token, line, col) = next.map_err(|err| {
+            Diagnostic::error(source_file, source, err.line, err.col, err.message)
+        })?;
         par.extra_mut().line = line;
         par.extra_mut().col = col;
-        par.parse(token).unwrap();
+        if let Err(message) = par.parse(token) {
+            return Err(Diagnostic::error(source_file, source, line, col, message));
+        }
+    }
+    let (line, col) = lex.position();
+    match par.end_of_input() {
+        Ok((root, _)) => Ok((root, lex.into_comments())),
+        Err(message) => Err(Diagnostic::error(source_file, source, line, col, message)),
+    }
+}
+
+fn base_dir(source_file: &Path) -> &Path {
+    source_file.parent().unwrap_or(Path::new(""))
+}
+
+/// Project file `path` refers to from `dir`, with `..` folded into the folder before it so a file
+/// included or imported from different folders ends up with the same path and the same sources.
+fn project_file(dir: &Path, path: &str) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in dir.join(path).components() {
+        match (component, result.components().next_back()) {
+            (Component::ParentDir, Some(Component::Normal(_))) => {
+                result.pop();
+            }
+            (Component::ParentDir, Some(Component::RootDir)) | (Component::CurDir, _) => {}
+            (component, _) => result.push(component),
+        }
     }
-    Ok(par.end_of_input().unwrap().0)
+    result
 }
 
-pub fn project_from_file<P: AsRef<Path>>(source_file: P) -> anyhow::Result<PackageTask> {
-    let tree = parse_file(source_file)?;
-    generate_project(&tree)
+/// `defines` are the `-D` values from the command line, they take precedence over `define`s in the files.
+/// `profile` selects the `profile` blocks and overrides that apply.
+pub fn project_from_file<P: AsRef<Path>>(
+    source_file: P,
+    defines: HashMap<String, String>,
+    profile: Option<&str>,
+) -> anyhow::Result<PackageTask> {
+    let (tree, source) = parse_file(&source_file)?;
+    generate_project(&tree, source_file.as_ref(), source, defines, profile)
 }
 
-pub fn task_from_file<P: AsRef<Path>>(source_file: P) -> anyhow::Result<Task> {
-    let tree = parse_file(source_file)?;
-    generate_task(&tree)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn project_file_folds_parent_dirs() {
+        let dir = Path::new("game/levels");
+        assert_eq!(
+            project_file(dir, "../common.pnd"),
+            Path::new("game/common.pnd")
+        );
+        assert_eq!(
+            project_file(dir, "./ui/../hud.pnd"),
+            Path::new("game/levels/hud.pnd")
+        );
+        assert_eq!(
+            project_file(Path::new(""), "../common.pnd"),
+            Path::new("../common.pnd")
+        );
+        assert_eq!(
+            project_file(Path::new("/"), "../common.pnd"),
+            Path::new("/common.pnd")
+        );
+    }
+
+    #[test]
+    fn syntax_error_spells_tokens() {
+        let source = "package p { tex a (from \"a.png\") ) }";
+        let err = parse_source(Path::new("test.pnd"), source).unwrap_err();
+        assert_eq!(
+            err.message,
+            "expected string, 'include', 'tex', 'font', 'sprite', 'intmap', 'extmap', 'profile', \
+             '}', 'preset', 'define', got ')'"
+        );
+    }
 }
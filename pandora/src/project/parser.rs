@@ -3,20 +3,36 @@ use pomelo::pomelo;
 pomelo! {
     %include {
         use crate::project::ast::*;
-        use crate::project::tasks::ResType;
-        use super::ParserState;
+        use shared::ResType;
+        use super::{ParserState, display_name};
     }
     %token #[derive(Clone,Debug)] pub enum Token {};
 
     %type Int i32;
-    %type Str String;
-    %type Name String;
-    %type int_list PropValue;
+    %type Hex i32;
+    %type Float f64;
+    %type Str (String, Span);
+    %type Name (String, Span);
+    %type Plus Span;
+    %type Minus Span;
+    %type Asterisk Span;
+    %type Slash Span;
+    %type KwPackage Span;
+    %type KwInclude Span;
+    %type KwTex Span;
+    %type KwFont Span;
+    %type KwSprite Span;
+    %type KwIntMap Span;
+    %type KwExtMap Span;
+    %type KwProfile Span;
+    %type RBracket Span;
     %type value PropValue;
-    %type param (String,PropValue);
+    %type expr Expr;
+    %type expr_list Vec<Expr>;
+    %type param (String, PropValue, Span);
     %type param_list Props;
     %type params Props;
-    %type class ResType;
+    %type class (ResType, Span);
     %type object Node;
     %type object_name Option<String>;
     %type root Node;
@@ -26,67 +42,90 @@ pomelo! {
     %type item_list Vec<Node>;
     %type valobj PropValue;
 
+    // A name right before `(` starts a source like `sheet(...)` rather than a list of expressions.
+    // So `origin w (h - 1)` is a source named `w`, two numbers are written `(w) (h - 1)` or `w h - 1`.
+    %nonassoc Name;
+    %nonassoc LParen;
+    %left Plus Minus;
+    %left Asterisk Slash;
+
     %extra_argument ParserState;
+    %error String;
 
     %syntax_error {
-        if let Some(sometoken) = token{
-            print!("[Ln {}, Col {}] ERROR: got {}, expecting", extra.line, extra.col, sometoken);
-        }else{
-            print!("[Ln {}, Col {}] ERROR: expecting", extra.line, extra.col);
-        }
-        for extoken in expected{
-            if let Some(sometoken) = extoken.token{
-                print!(" {}", sometoken);
-            }else{
-                print!(" {}", extoken.name);
-            }
+        let found = match token {
+            Some(sometoken) => sometoken.to_string(),
+            None => String::from("end of file"),
+        };
+        let mut expected: Vec<&str> = expected.map(|extoken| display_name(extoken.name)).collect();
+        expected.dedup();
+        if expected.is_empty() {
+            Err(format!("unexpected {}", found))
+        } else {
+            Err(format!("expected {}, got {}", expected.join(", "), found))
         }
-        println!();
-        Err(())
+    }
+
+    %parse_fail {
+        String::from("unexpected end of file")
     }
 
     root ::= package(pkg) { pkg };
     root ::= object(obj) { obj };
 
-    package ::= KwPackage Name(n) LBracket item_list(il) RBracket { Node::Package(n,None,il) };
-    package ::= KwPackage Name(n) params(p) LBracket item_list(il) RBracket { Node::Package(n,Some(p),il) };
+    package ::= KwPackage(k) Name(n) LBracket item_list(il) RBracket(e) { Node::Package(n.0,None,il,k,e) };
+    package ::= KwPackage(k) Name(n) params(p) LBracket item_list(il) RBracket(e) { Node::Package(n.0,Some(p),il,k,e) };
     item_list ::= item_list(mut il) item(it) { il.push(it); il };
     item_list ::= item(it) { vec![it] };
 
     item ::= folder(fl) { fl };
     item ::= object(obj) { obj };
-
-    folder ::= Str(s) params(p) LBracket item_list(il) RBracket { Node::Folder(s, Some(p), il) };
-    folder ::= Str(s) LBracket item_list(il) RBracket { Node::Folder(s, None, il) };
-    object ::= class(c) Name(n) { Node::Object(c, Some(n), None) };
-    object ::= class(c) object_name(n) params(p) { Node::Object(c, n, Some(p)) };
-    object ::= class(c) object_name(n) KwImport Str(s) { Node::ObjectImport(c, n, s) };
+    item ::= KwInclude(k) Str(s) { Node::Include(s.0, k) };
+    item ::= KwPreset Name(n) params(p) { Node::Preset(n.0, p, n.1) };
+    item ::= KwDefine Name(n) Str(s) { Node::Define(n.0, s.0, n.1) };
+    item ::= KwProfile(k) Name(n) LBracket item_list(il) RBracket(e) { Node::Profile(n.0, il, k, e) };
+
+    folder ::= Str(s) params(p) LBracket item_list(il) RBracket(e) { Node::Folder(s.0, Some(p), il, s.1, e) };
+    folder ::= Str(s) LBracket item_list(il) RBracket(e) { Node::Folder(s.0, None, il, s.1, e) };
+    object ::= class(c) Name(n) { Node::Object(c.0, Some(n.0), None, c.1) };
+    object ::= class(c) object_name(n) params(p) { Node::Object(c.0, n, Some(p), c.1) };
+    object ::= class(c) object_name(n) KwImport Str(s) { Node::ObjectImport(c.0, n, s.0, c.1) };
     object_name ::= { None };
-    object_name ::= Name(n) { Some(n) };
+    object_name ::= Name(n) { Some(n.0) };
     object_name ::= Asterisk { Some(String::from("*")) };
 
-    class ::= KwTex { ResType::Texture };
-    class ::= KwFont { ResType::Font };
-    class ::= KwSprite { ResType::Sprite };
-    class ::= KwIntMap { ResType::IntMap };
-    class ::= KwExtMap { ResType::ExtMap };
+    class ::= KwTex(k) { (ResType::Texture, k) };
+    class ::= KwFont(k) { (ResType::Font, k) };
+    class ::= KwSprite(k) { (ResType::Sprite, k) };
+    class ::= KwIntMap(k) { (ResType::IntMap, k) };
+    class ::= KwExtMap(k) { (ResType::ExtMap, k) };
 
     params ::= LParen param_list(pl) RParen { pl };
     param_list ::= param_list(mut list) Comma param(p) { list.push(p); list };
     param_list ::= param(p) { vec![p] };
 
-    param ::= Name(key) { (key,PropValue::Empty) };
-    param ::= Name(key) value(val) { (key, val) };
-    value ::= Str(st) { PropValue::Str(st) };
-    value ::= Name(n) { PropValue::Const(const_from_string(n)) };
-    value ::= int_list(il) { il };
+    param ::= Name(key) { (key.0, PropValue::Empty, key.1) };
+    param ::= Name(key) value(val) { (key.0, val, key.1) };
+    param ::= KwUse Name(n) { (String::from("use"), PropValue::Str(n.0), n.1) };
+    param ::= KwProfile Name(n) params(p) { (String::from("profile"), PropValue::ValObj(n.0, p), n.1) };
+    value ::= Str(st) { PropValue::Str(st.0) };
+    value ::= expr_list(el) { value_from_exprs(el) };
     value ::= valobj(vo) { vo };
 
-    int_list ::= Int(v) { PropValue::Int(v) };
-    int_list ::= Int(v1) Int(v2) { PropValue::Int2(v1,v2) };
-    int_list ::= Int(v1) Int(v2) Int(v3) Int(v4) { PropValue::Int4(v1,v2,v3,v4) };
+    expr_list ::= expr_list(mut el) expr(e) { el.push(e); el };
+    expr_list ::= expr(e) { vec![e] };
 
-    valobj ::= Name(n) params(pl) { PropValue::ValObj(n, pl) };
+    expr ::= Int(v) { Expr::Int(v) };
+    expr ::= Hex(v) { Expr::Hex(v) };
+    expr ::= Float(v) { Expr::Float(v) };
+    expr ::= Name(n) { Expr::Var(n.0, n.1) };
+    expr ::= LParen expr(e) RParen { Expr::Paren(Box::new(e)) };
+    expr ::= expr(a) Plus(op) expr(b) { Expr::Binary(Box::new(a), BinOp::Add, Box::new(b), op) };
+    expr ::= expr(a) Minus(op) expr(b) { Expr::Binary(Box::new(a), BinOp::Sub, Box::new(b), op) };
+    expr ::= expr(a) Asterisk(op) expr(b) { Expr::Binary(Box::new(a), BinOp::Mul, Box::new(b), op) };
+    expr ::= expr(a) Slash(op) expr(b) { Expr::Binary(Box::new(a), BinOp::Div, Box::new(b), op) };
+
+    valobj ::= Name(n) params(pl) { PropValue::ValObj(n.0, pl) };
 }
 
 pub use parser::Parser;
@@ -94,24 +133,66 @@ pub use parser::Token;
 
 impl std::fmt::Display for parser::Token {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Token::Int(_) => write!(f, "number"),
-            Token::Str(_) => write!(f, "string"),
-            Token::Name(_) => write!(f, "name"),
-            Token::KwPackage => write!(f, "'package'"),
-            Token::LBracket => write!(f, "'{{'"),
-            Token::RBracket => write!(f, "'}}'"),
-            Token::KwImport => write!(f, "'import'"),
-            Token::Asterisk => write!(f, "'*'"),
-            Token::KwTex => write!(f, "'tex'"),
-            Token::KwFont => write!(f, "'font'"),
-            Token::KwSprite => write!(f, "'sprite'"),
-            Token::KwIntMap => write!(f, "'intmap'"),
-            Token::KwExtMap => write!(f, "'extmap'"),
-            Token::LParen => write!(f, "'('"),
-            Token::RParen => write!(f, "')'"),
-            Token::Comma => write!(f, "','"),
-        }
+        let name = match self {
+            Token::Int(_) => "Int",
+            Token::Hex(_) => "Hex",
+            Token::Float(_) => "Float",
+            Token::Str(_) => "Str",
+            Token::Name(_) => "Name",
+            Token::KwPackage(_) => "KwPackage",
+            Token::LBracket => "LBracket",
+            Token::RBracket(_) => "RBracket",
+            Token::KwImport => "KwImport",
+            Token::KwInclude(_) => "KwInclude",
+            Token::KwPreset => "KwPreset",
+            Token::KwUse => "KwUse",
+            Token::KwDefine => "KwDefine",
+            Token::Asterisk(_) => "Asterisk",
+            Token::Plus(_) => "Plus",
+            Token::Minus(_) => "Minus",
+            Token::Slash(_) => "Slash",
+            Token::KwTex(_) => "KwTex",
+            Token::KwFont(_) => "KwFont",
+            Token::KwSprite(_) => "KwSprite",
+            Token::KwIntMap(_) => "KwIntMap",
+            Token::KwExtMap(_) => "KwExtMap",
+            Token::KwProfile(_) => "KwProfile",
+            Token::LParen => "LParen",
+            Token::RParen => "RParen",
+            Token::Comma => "Comma",
+        };
+        f.write_str(display_name(name))
+    }
+}
+
+/// How the token called `name` in the grammar is spelled in syntax errors.
+fn display_name(name: &str) -> &str {
+    match name {
+        "Int" | "Hex" | "Float" => "number",
+        "Str" => "string",
+        "Name" => "name",
+        "KwPackage" => "'package'",
+        "LBracket" => "'{'",
+        "RBracket" => "'}'",
+        "KwImport" => "'import'",
+        "KwInclude" => "'include'",
+        "KwPreset" => "'preset'",
+        "KwUse" => "'use'",
+        "KwDefine" => "'define'",
+        "Asterisk" => "'*'",
+        "Plus" => "'+'",
+        "Minus" => "'-'",
+        "Slash" => "'/'",
+        "KwTex" => "'tex'",
+        "KwFont" => "'font'",
+        "KwSprite" => "'sprite'",
+        "KwIntMap" => "'intmap'",
+        "KwExtMap" => "'extmap'",
+        "KwProfile" => "'profile'",
+        "LParen" => "'('",
+        "RParen" => "')'",
+        "Comma" => "','",
+        _ => name,
     }
 }
 
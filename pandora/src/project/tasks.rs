@@ -1,76 +1,174 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use anyhow::anyhow;
-use path_slash::PathBufExt;
-use shared::DitheringMethod;
+use anyhow::{Context, anyhow};
+use path_slash::{PathBufExt, PathExt};
+use shared::{DitheringMethod, ResType};
 
-use crate::project::ast::{Node, PropConst, PropValue, Props};
+use crate::{
+    build::source_size,
+    project::{
+        ast::{Expr, Node, PropConst, PropValue, Props, Span},
+        base_dir,
+        batch::{BatchRange, NumberPattern},
+        defines::Defines,
+        diagnostics::Diagnostic,
+        expr::{ExprError, Scope, check_defines, evaluate, mentions_size},
+        format::format_value,
+        parse_file, project_file,
+        validate::{check_value, takes_fractions},
+    },
+};
 
-#[derive(Debug, Copy, Clone)]
-pub enum ResType {
-    Texture,
-    Font,
-    Sprite,
-    IntMap,
-    ExtMap,
-}
-
-#[derive(Debug)]
-enum TaskKind {
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaskKind {
     TextureConvert(TextureParams),
     FontConvert(FontParams),
     SpriteConvert(SpriteParams),
     CopyFile(ResType),
 }
 
-#[derive(Debug)]
-enum SourceEx {
+#[derive(Debug, Clone, PartialEq)]
+pub enum SourceEx {
     Single,
-    Batch,
-    Sheet(u32, u32),
+    /// Numbered sequence of frame files, the task source is the pattern.
+    Batch(BatchRange),
+    /// Whole sheet, its cells become sprite frames or font glyphs.
+    Sheet(SheetLayout),
+    /// Single cell of a sheet, numbered row by row.
+    SheetCell(SheetLayout, u32),
 }
 
-#[derive(Debug)]
+/// Grid of equally sized cells on a source image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SheetLayout {
+    pub cols: u32,
+    pub rows: u32,
+    /// Empty pixels around the whole grid.
+    pub margin: u32,
+    /// Empty pixels between neighbouring cells.
+    pub padding: u32,
+}
+
+/// Most cells a sheet can have. Texture sheets become one task and one package entry per cell.
+pub const MAX_SHEET_CELLS: u32 = 65536;
+
+impl SheetLayout {
+    /// Number of cells, fails above [`MAX_SHEET_CELLS`].
+    pub fn cell_count(&self) -> anyhow::Result<u32> {
+        self.cols
+            .checked_mul(self.rows)
+            .filter(|&count| count <= MAX_SHEET_CELLS)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Sheet of {}x{} cells is too large, at most {} cells are supported",
+                    self.cols,
+                    self.rows,
+                    MAX_SHEET_CELLS
+                )
+            })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Task {
-    name: Option<String>,
-    src: String,
-    dest: String,
-    kind: TaskKind,
-    src_ex: SourceEx,
+    pub name: Option<String>,
+    pub src: String,
+    pub dest: String,
+    pub kind: TaskKind,
+    pub src_ex: SourceEx,
+    /// Params using the size of the source image, `kind` has their defaults until [`Task::with_source_size`].
+    pub sized: Option<SizedParams>,
 }
 
-#[derive(Debug)]
-struct TextureParams {
-    transparent: bool,
-    dithering: DitheringMethod,
+impl Task {
+    /// Package entries are keyed by the task destination folder plus the object name.
+    pub fn entry_path(&self) -> anyhow::Result<String> {
+        let name = match &self.name {
+            Some(name) => name.clone(),
+            None => Path::new(&self.src)
+                .file_stem()
+                .ok_or_else(|| anyhow!("Can't derive a name from \"{}\"", self.src))?
+                .to_string_lossy()
+                .into_owned(),
+        };
+        Ok(format!("{}/{}", self.dest.trim_end_matches('/'), name))
+    }
+
+    /// Copy with the params that use `w` and `h` evaluated, this reads the size of the source image.
+    pub fn with_source_size(&self) -> anyhow::Result<Task> {
+        let Some(sized) = &self.sized else {
+            return Ok(self.clone());
+        };
+        let src = PathBuf::from(&self.src);
+        let size = source_size(&src, &self.src_ex)
+            .with_context(|| format!("Can't evaluate \"{}\"", sized.exprs[0].0))?;
+        make_task(
+            sized.res_type,
+            &self.name,
+            src,
+            self.src_ex.clone(),
+            &sized.context,
+            Some(size),
+        )
+    }
 }
 
-#[derive(Debug)]
-struct FontParams {
-    transparent: bool,
-    dithering: DitheringMethod,
-    cols: u32,
-    rows: u32,
-    border_left: Option<u32>,
-    border_right: Option<u32>,
-    border_top: Option<u32>,
-    border_bottom: Option<u32>,
-    start_char: u32,
-    end_char: u32,
-    fallback_char: u32,
-    letter_space: i32,
-    line_height: i32,
+/// Params whose expressions use the width or height of the source image. They are evaluated when the task is
+/// built, so checking or listing a project doesn't read any images.
+#[derive(Debug, Clone)]
+pub struct SizedParams {
+    res_type: ResType,
+    /// Object params with every other expression evaluated.
+    context: TaskParams,
+    /// Each key with its expressions and the values of the defines they use.
+    exprs: Vec<(String, String)>,
 }
 
-#[derive(Debug)]
-struct SpriteParams {
-    transparent: bool,
-    dithering: DitheringMethod,
-    cols: u32,
-    rows: u32,
-    origin_x: i32,
-    origin_y: i32,
-    frame_time: f32,
+/// The other params are part of the task kind already.
+impl PartialEq for SizedParams {
+    fn eq(&self, other: &Self) -> bool {
+        self.res_type == other.res_type && self.exprs == other.exprs
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextureParams {
+    pub transparent: bool,
+    pub dithering: DitheringMethod,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FontParams {
+    pub transparent: bool,
+    pub dithering: DitheringMethod,
+    pub cols: u32,
+    pub rows: u32,
+    pub border_left: Option<u32>,
+    pub border_right: Option<u32>,
+    pub border_top: Option<u32>,
+    pub border_bottom: Option<u32>,
+    pub start_char: u32,
+    /// `None` for batch fonts that end with their last frame, see [`FontParams::with_frames`].
+    pub end_char: Option<u32>,
+    /// `None` for batch fonts that fall back to their last character.
+    pub fallback_char: Option<u32>,
+    pub letter_space: i32,
+    pub line_height: i32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpriteParams {
+    pub transparent: bool,
+    pub dithering: DitheringMethod,
+    pub cols: u32,
+    pub rows: u32,
+    pub origin_x: i32,
+    pub origin_y: i32,
+    pub frame_time: f32,
 }
 
 impl Default for TextureParams {
@@ -83,7 +181,7 @@ impl Default for TextureParams {
 }
 
 impl TextureParams {
-    pub fn apply(&mut self, params: &TaskParams) {
+    fn apply(&mut self, params: &TaskParams) {
         if params.params.contains_key("transparent") {
             self.transparent = true;
         }
@@ -106,8 +204,8 @@ impl Default for FontParams {
             border_top: None,
             border_bottom: None,
             start_char: 0,
-            end_char: 255,
-            fallback_char: 255,
+            end_char: Some(255),
+            fallback_char: Some(255),
             letter_space: 1,
             line_height: 0,
         }
@@ -115,7 +213,7 @@ impl Default for FontParams {
 }
 
 impl FontParams {
-    pub fn apply(&mut self, params: &TaskParams) {
+    fn apply(&mut self, params: &TaskParams) {
         if params.params.contains_key("transparent") {
             self.transparent = true;
         }
@@ -137,11 +235,11 @@ impl FontParams {
         }
 
         if let Some(&PropValue::Int(val)) = params.params.get("end_char") {
-            self.end_char = val as u32;
+            self.end_char = Some(val as u32);
         }
 
         if let Some(&PropValue::Int(val)) = params.params.get("fallback_char") {
-            self.fallback_char = val as u32;
+            self.fallback_char = Some(val as u32);
         }
 
         if let Some(&PropValue::Int(val)) = params.params.get("letter_space") {
@@ -184,6 +282,22 @@ impl FontParams {
     }
 }
 
+impl FontParams {
+    /// Params of a batch font once its frames are known: every frame is one glyph, the grid gets as many rows
+    /// as the frames need, and an open character range ends with the last frame.
+    pub fn with_frames(&self, frame_count: u32) -> FontParams {
+        let end_char = self
+            .end_char
+            .unwrap_or(self.start_char + frame_count.max(1) - 1);
+        FontParams {
+            rows: frame_count.div_ceil(self.cols.max(1)),
+            end_char: Some(end_char),
+            fallback_char: Some(self.fallback_char.unwrap_or(end_char)),
+            ..self.clone()
+        }
+    }
+}
+
 impl Default for SpriteParams {
     fn default() -> Self {
         Self {
@@ -199,7 +313,7 @@ impl Default for SpriteParams {
 }
 
 impl SpriteParams {
-    pub fn apply(&mut self, params: &TaskParams) {
+    fn apply(&mut self, params: &TaskParams) {
         if params.params.contains_key("transparent") {
             self.transparent = true;
         }
@@ -221,8 +335,10 @@ impl SpriteParams {
             self.origin_y = vy;
         }
 
-        if let Some(&PropValue::Int(val)) = params.params.get("fps") {
-            self.frame_time = 1.0 / (val as f32);
+        match params.params.get("fps") {
+            Some(&PropValue::Int(val)) => self.frame_time = 1.0 / (val as f32),
+            Some(&PropValue::Float(val)) => self.frame_time = (1.0 / val) as f32,
+            _ => {}
         }
     }
 }
@@ -240,6 +356,17 @@ fn convert_dithering(input: PropConst) -> DitheringMethod {
 pub struct PackageTask {
     pub filename: String,
     pub tasks: Vec<Task>,
+    /// Project files read to generate the tasks: the package file itself, its includes and imports.
+    pub project_files: Vec<PathBuf>,
+    /// Profile names the project files mention, a `--profile` missing here is most likely a typo.
+    pub profiles: HashSet<String>,
+}
+
+/// Project file with its text, kept for the diagnostics of params evaluated after it was parsed.
+#[derive(Debug, Default)]
+struct SourceFile {
+    path: PathBuf,
+    text: String,
 }
 
 #[derive(Clone, Debug)]
@@ -247,234 +374,680 @@ struct TaskParams {
     src: PathBuf,
     dest: PathBuf,
     params: HashMap<String, PropValue>,
+    /// Project file and key position each param was written at, evaluation errors point there.
+    param_origins: HashMap<String, (Arc<SourceFile>, Span)>,
+    /// Project file being processed.
+    file: Arc<SourceFile>,
+    /// Directory of the project file being processed, imports are resolved against it.
+    origin: PathBuf,
+    /// Canonical paths of the project files being included, outermost first.
+    includes: Vec<PathBuf>,
+    /// Presets declared in the project file being processed.
+    presets: HashMap<String, Props>,
+    defines: Defines,
+    /// Profile selected on the command line, its blocks and property overrides apply.
+    profile: Option<String>,
 }
 
 impl TaskParams {
-    pub fn new() -> TaskParams {
+    pub fn new(base_dir: &Path) -> TaskParams {
         TaskParams {
-            src: PathBuf::new(),
+            src: base_dir.to_path_buf(),
             dest: PathBuf::from("/"),
             params: HashMap::new(),
+            param_origins: HashMap::new(),
+            file: Arc::default(),
+            origin: base_dir.to_path_buf(),
+            includes: Vec::new(),
+            presets: HashMap::new(),
+            defines: Defines::default(),
+            profile: None,
+        }
+    }
+
+    fn is_active(&self, profile: &str) -> bool {
+        self.profile.as_deref() == Some(profile)
+    }
+
+    /// Picks up the presets and defines declared at package level of a project file.
+    /// The ones in the block of the active profile come last, overriding the others.
+    fn enter_package(&mut self, childs: &[Node]) -> anyhow::Result<()> {
+        self.presets.clear();
+        self.declare(childs, false)?;
+        self.declare(childs, true)
+    }
+
+    fn declare(&mut self, childs: &[Node], in_profile: bool) -> anyhow::Result<()> {
+        for node in childs {
+            match node {
+                Node::Preset(name, props, _) if !in_profile => {
+                    self.presets.insert(name.clone(), props.clone());
+                }
+                Node::Define(name, value, _) if !in_profile => self
+                    .defines
+                    .define(name, value)
+                    .with_context(|| format!("Can't define \"{}\"", name))?,
+                Node::Profile(name, items, ..) if in_profile && self.is_active(name) => {
+                    self.declare(items, false)?
+                }
+                _ => {}
+            }
         }
+        Ok(())
     }
 
-    pub fn append_props(&mut self, other: &Props, dest: Option<String>) {
+    pub fn append_props(&mut self, other: &Props, dest: Option<String>) -> anyhow::Result<()> {
         if let Some(dest_path) = dest {
             self.dest.push(dest_path)
         }
-        for (key, value) in other {
-            if key == "from" {
-                if let PropValue::Str(path) = value {
-                    self.src.push(path);
-                } else {
-                    self.params.insert(key.clone(), value.clone());
+        self.merge_props(other, &mut Vec::new())
+    }
+
+    /// Applies `use`d presets first, so the props written next to them take precedence.
+    /// Overrides of the active profile come last and win over both.
+    fn merge_props(&mut self, other: &Props, used: &mut Vec<String>) -> anyhow::Result<()> {
+        for (key, value, _) in other {
+            if key != "use" {
+                continue;
+            }
+            let PropValue::Str(name) = value else {
+                continue;
+            };
+            if used.contains(name) {
+                return Err(anyhow!("Preset \"{}\" uses itself", name));
+            }
+            let preset = self
+                .presets
+                .get(name)
+                .cloned()
+                .ok_or_else(|| anyhow!("Unknown preset \"{}\"", name))?;
+            used.push(name.clone());
+            self.merge_props(&preset, used)?;
+            used.pop();
+        }
+
+        for (key, value, span) in other {
+            if key == "use" || key == "profile" {
+                continue;
+            }
+            let value = self.defines.expand_value(value)?;
+            if key == "from"
+                && let PropValue::Str(path) = value
+            {
+                self.src.push(path);
+                continue;
+            }
+            self.params.insert(key.clone(), value);
+            self.param_origins
+                .insert(key.clone(), (self.file.clone(), *span));
+        }
+
+        for (key, value, _) in other {
+            if let ("profile", PropValue::ValObj(name, props)) = (key.as_str(), value)
+                && self.is_active(name)
+            {
+                self.merge_props(props, used)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy with the expressions in the params replaced by their values, `w` and `h` being the image `size`.
+    /// Without the size, expressions using it are kept and also returned, as [`SizedParams::exprs`].
+    fn evaluated(
+        &self,
+        size: Option<(u32, u32)>,
+    ) -> anyhow::Result<(TaskParams, Vec<(String, String)>)> {
+        let mut result = self.clone();
+        let mut sized = Vec::new();
+        // In key order, so the same param is reported on every run when several are wrong.
+        let mut keys: Vec<&String> = self.params.keys().collect();
+        keys.sort();
+        for key in keys {
+            let Some(PropValue::Expr(exprs)) = self.params.get(key) else {
+                continue;
+            };
+            let origin = self
+                .param_origins
+                .get(key)
+                .map(|(file, span)| (file.as_ref(), *span));
+            if size.is_none() && exprs.iter().any(mentions_size) {
+                let names = check_defines(exprs, &self.scope(None))
+                    .map_err(|err| expr_error(err, origin))?;
+                let mut text = format_value(&PropValue::Expr(exprs.clone()));
+                for name in names {
+                    text.push_str(&format!(
+                        ", {} {}",
+                        name,
+                        self.defines.get(name).unwrap_or_default()
+                    ));
                 }
-            } else {
-                self.params.insert(key.clone(), value.clone());
+                sized.push((key.clone(), text));
+                continue;
             }
+            let value = self.evaluate(exprs, None, key, size, origin)?;
+            result.params.insert(key.clone(), value);
         }
+        Ok((result, sized))
+    }
+
+    fn scope(&self, size: Option<(u32, u32)>) -> Scope<'_> {
+        Scope {
+            defines: &self.defines,
+            size,
+        }
+    }
+
+    /// Value of `key` in `source`, or among the object params without it. Problems are reported as diagnostics
+    /// at the name or operator causing them, or at `origin`, the file and position the key was written at.
+    fn evaluate(
+        &self,
+        exprs: &[Expr],
+        source: Option<&str>,
+        key: &str,
+        size: Option<(u32, u32)>,
+        origin: Option<(&SourceFile, Span)>,
+    ) -> anyhow::Result<PropValue> {
+        let fractions = source.is_none() && takes_fractions(key);
+        let result = evaluate(exprs, &self.scope(size), fractions).and_then(|value| {
+            match check_value(source, key, &value) {
+                Ok(()) => Ok(value),
+                Err(message) => Err(ExprError {
+                    span: None,
+                    message,
+                }),
+            }
+        });
+        result.map_err(|err| expr_error(err, origin))
+    }
+}
+
+/// Reports `err` the way [`TaskParams::evaluate`] describes.
+fn expr_error(err: ExprError, origin: Option<(&SourceFile, Span)>) -> anyhow::Error {
+    match origin {
+        Some((file, key_span)) => {
+            let span = err.span.unwrap_or(key_span);
+            Diagnostic::error(&file.path, &file.text, span.line, span.col, err.message).into()
+        }
+        None => anyhow!("{}", err.message),
     }
 }
 
-fn process_node(node: &Node, package: &mut PackageTask, context: &TaskParams) {
+fn process_node(
+    node: &Node,
+    package: &mut PackageTask,
+    context: &TaskParams,
+) -> anyhow::Result<()> {
     let mut own_context = context.clone();
     match node {
-        Node::Folder(path, props, childs) => {
+        Node::Folder(path, props, childs, ..) => {
+            own_context.dest.push(own_context.defines.expand(path)?);
             if let Some(someprops) = props {
-                own_context.append_props(someprops, Some(path.clone()));
+                own_context.append_props(someprops, None)?;
             }
             for node in childs {
-                process_node(node, package, &own_context);
+                process_node(node, package, &own_context)?;
             }
         }
-        Node::Object(res_type, name, props) => {
+        Node::Object(res_type, name, props, _) => {
             if let Some(someprops) = props {
-                own_context.append_props(someprops, None);
+                own_context.append_props(someprops, None)?;
             }
-            let src_ex = {
-                if let Some(PropValue::ValObj(name, props)) = own_context.params.get("from") {
-                    let mut file: String = "".to_string();
-                    let mut cols: u32 = 16;
-                    let mut rows: u32 = 16;
-
-                    for (key, value) in props {
-                        match key.as_str() {
-                            "file" => {
-                                if let PropValue::Str(filename) = value {
-                                    file = filename.to_string();
-                                }
-                            }
-                            "cols" => {
-                                if let PropValue::Int(val) = value {
-                                    cols = *val as u32;
-                                }
-                            }
-                            "rows" => {
-                                if let PropValue::Int(val) = value {
-                                    rows = *val as u32;
-                                }
-                            }
-                            _ => {}
-                        }
-                    }
-
-                    own_context.src.push(file);
-
-                    match name.as_str() {
-                        "batch" => SourceEx::Batch,
-                        "sheet" => SourceEx::Sheet(cols, rows),
-                        _ => SourceEx::Single,
-                    }
-                } else {
-                    SourceEx::Single
-                }
+            add_object(*res_type, name, &own_context, package)?;
+        }
+        Node::ObjectImport(res_type, name, path, _) => {
+            let path = &own_context.defines.expand(path)?;
+            package
+                .project_files
+                .push(project_file(&own_context.origin, path));
+            let (name, tree) = import_object(*res_type, name, path, &mut own_context)?;
+            collect_profiles(&tree, &mut package.profiles);
+            add_object(*res_type, &name, &own_context, package)?;
+        }
+        Node::Include(path, _) => {
+            let path = &own_context.defines.expand(path)?;
+            let include_file = project_file(&own_context.origin, path);
+            let canonical = include_file
+                .canonicalize()
+                .with_context(|| format!("Can't include \"{}\"", path))?;
+            if own_context.includes.contains(&canonical) {
+                return Err(anyhow!("Include cycle: \"{}\" includes itself", path));
+            }
+            package.project_files.push(include_file.clone());
+            let (tree, text) =
+                parse_file(&include_file).with_context(|| format!("Can't include \"{}\"", path))?;
+            collect_profiles(&tree, &mut package.profiles);
+            let Node::Package(_, props, childs, ..) = &tree else {
+                return Err(anyhow!("Included file \"{}\" is not a package", path));
             };
 
-            if own_context.params.contains_key("raw") {
-                package.tasks.push(Task {
-                    name: name.clone(),
-                    src: own_context.src.to_slash().unwrap().into_owned(),
-                    dest: own_context.dest.to_slash().unwrap().into_owned(),
-                    kind: TaskKind::CopyFile(*res_type),
-                    src_ex,
-                });
-            } else {
-                let kind = match res_type {
-                    ResType::Texture => {
-                        let mut tex_params = TextureParams::default();
-                        tex_params.apply(&own_context);
-                        TaskKind::TextureConvert(tex_params)
-                    }
-                    ResType::Font => {
-                        let mut font_params = FontParams::default();
-                        font_params.apply(&own_context);
-                        TaskKind::FontConvert(font_params)
-                    }
-                    ResType::Sprite => {
-                        let mut sprite_params = SpriteParams::default();
-                        sprite_params.apply(&own_context);
-                        TaskKind::SpriteConvert(sprite_params)
-                    }
-                    ResType::IntMap => TaskKind::CopyFile(ResType::IntMap),
-                    ResType::ExtMap => TaskKind::CopyFile(ResType::ExtMap),
-                };
-                package.tasks.push(Task {
-                    name: name.clone(),
-                    src: own_context.src.to_slash().unwrap().into_owned(),
-                    dest: own_context.dest.to_slash().unwrap().into_owned(),
-                    kind,
-                    src_ex,
-                });
+            own_context.includes.push(canonical);
+            own_context.origin = base_dir(&include_file).to_path_buf();
+            own_context.src = own_context.origin.clone();
+            own_context.file = Arc::new(SourceFile {
+                path: include_file,
+                text,
+            });
+            own_context.enter_package(childs)?;
+            if let Some(someprops) = props {
+                own_context.append_props(someprops, None)?;
+            }
+            for node in childs {
+                process_node(node, package, &own_context)
+                    .with_context(|| format!("In \"{}\"", path))?;
+            }
+        }
+        Node::Profile(name, childs, ..) => {
+            if own_context.is_active(name) {
+                for node in childs {
+                    process_node(node, package, &own_context)?;
+                }
             }
         }
-        _ => {}
+        Node::Package(..) | Node::Preset(..) | Node::Define(..) => {}
     }
+    Ok(())
 }
 
-fn process_valobj(context: &TaskParams) -> (Option<String>, SourceEx) {
-    if let Some(PropValue::ValObj(name, props)) = context.params.get("from") {
-        let mut file: Option<String> = None;
-        let mut cols: u32 = 16;
-        let mut rows: u32 = 16;
+/// Adds the names of the profile blocks and overrides in `node` to `profiles`.
+fn collect_profiles(node: &Node, profiles: &mut HashSet<String>) {
+    fn from_props(props: &Props, profiles: &mut HashSet<String>) {
+        for (key, value, _) in props {
+            if let ("profile", PropValue::ValObj(name, props)) = (key.as_str(), value) {
+                profiles.insert(name.clone());
+                from_props(props, profiles);
+            }
+        }
+    }
 
-        for (key, value) in props {
-            match key.as_str() {
-                "file" => {
-                    if let PropValue::Str(filename) = value {
-                        file = Some(filename.to_string());
-                    }
-                }
-                "cols" => {
-                    if let PropValue::Int(val) = value {
-                        cols = *val as u32;
-                    }
-                }
-                "rows" => {
-                    if let PropValue::Int(val) = value {
-                        rows = *val as u32;
-                    }
-                }
-                _ => {}
+    match node {
+        Node::Package(_, props, childs, ..) | Node::Folder(_, props, childs, ..) => {
+            if let Some(props) = props {
+                from_props(props, profiles);
+            }
+            for child in childs {
+                collect_profiles(child, profiles);
+            }
+        }
+        Node::Profile(name, childs, ..) => {
+            profiles.insert(name.clone());
+            for child in childs {
+                collect_profiles(child, profiles);
             }
         }
+        Node::Object(_, _, Some(props), _) | Node::Preset(_, props, _) => {
+            from_props(props, profiles)
+        }
+        Node::Object(_, _, None, _)
+        | Node::ObjectImport(..)
+        | Node::Include(..)
+        | Node::Define(..) => {}
+    }
+}
+
+/// Parses the object file `path` points to and applies its props to `context`, whose sources then resolve
+/// relative to the imported file. Package presets don't reach into imported files.
+/// Returns the object name, the one in the imported file wins over `name` at the import, and the syntax tree.
+fn import_object(
+    res_type: ResType,
+    name: &Option<String>,
+    path: &str,
+    context: &mut TaskParams,
+) -> anyhow::Result<(Option<String>, Node)> {
+    let import_file = project_file(&context.origin, path);
+    let (tree, text) =
+        parse_file(&import_file).with_context(|| format!("Can't import \"{}\"", path))?;
+    let Node::Object(import_type, import_name, import_props, _) = &tree else {
+        return Err(anyhow!("Imported file \"{}\" is not an object", path));
+    };
+    if *import_type != res_type {
+        return Err(anyhow!(
+            "Imported file \"{}\" defines {:?}, expected {:?}",
+            path,
+            import_type,
+            res_type
+        ));
+    }
+
+    context.origin = base_dir(&import_file).to_path_buf();
+    context.src = context.origin.clone();
+    context.file = Arc::new(SourceFile {
+        path: import_file,
+        text,
+    });
+    context.presets.clear();
+    if let Some(someprops) = import_props {
+        context.append_props(someprops, None)?;
+    }
+    Ok((import_name.clone().or_else(|| name.clone()), tree))
+}
 
-        match name.as_str() {
-            "batch" => (file, SourceEx::Batch),
-            "sheet" => (file, SourceEx::Sheet(cols, rows)),
-            _ => (file, SourceEx::Single),
+/// Drops repeated definitions of the same entry, fails if they disagree on source or params.
+fn dedup_tasks(tasks: Vec<Task>) -> anyhow::Result<Vec<Task>> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let mut result: Vec<Task> = Vec::new();
+    for task in tasks {
+        let path = task.entry_path()?;
+        if let Some(&index) = seen.get(&path) {
+            let other = &result[index];
+            if *other != task {
+                return Err(anyhow!(
+                    "\"{}\" is defined twice with different sources or settings",
+                    path
+                ));
+            }
+            continue;
         }
+        seen.insert(path, result.len());
+        result.push(task);
+    }
+    Ok(result)
+}
+
+fn add_object(
+    res_type: ResType,
+    name: &Option<String>,
+    context: &TaskParams,
+    package: &mut PackageTask,
+) -> anyhow::Result<()> {
+    let (src, src_ex) = process_valobj(context)?;
+    let sources = if name.as_deref() == Some("*") {
+        expand_wildcard(&src, context)?
     } else {
-        (None, SourceEx::Single)
+        vec![(name.clone(), src)]
+    };
+    for (name, src) in sources {
+        let task = make_task(res_type, &name, src, src_ex.clone(), context, None)?;
+        if let (TaskKind::TextureConvert(_), SourceEx::Sheet(layout)) = (&task.kind, &task.src_ex) {
+            let layout = layout.clone();
+            package.tasks.extend(expand_sheet(task, layout, context)?);
+        } else {
+            package.tasks.push(task);
+        }
     }
+    Ok(())
 }
 
-fn process_object(node: &Node, context: &TaskParams) -> anyhow::Result<Task> {
-    let mut own_context = context.clone();
-    if let Node::Object(res_type, name, props) = node {
-        if let Some(someprops) = props {
-            own_context.append_props(someprops, None);
+/// Resolves `from` against the context source path and reads the layout of `sheet(...)` and `batch(...)` sources.
+fn process_valobj(context: &TaskParams) -> anyhow::Result<(PathBuf, SourceEx)> {
+    let mut src = context.src.clone();
+    let Some(PropValue::ValObj(name, props)) = context.params.get("from") else {
+        return Ok((src, SourceEx::Single));
+    };
+
+    let mut cols: u32 = 16;
+    let mut rows: u32 = 16;
+    let mut margin: u32 = 0;
+    let mut padding: u32 = 0;
+    let mut start: Option<i32> = None;
+    let mut end: Option<i32> = None;
+    let mut step: i32 = 1;
+
+    for (key, value, span) in props {
+        let evaluated;
+        let value = match value {
+            PropValue::Expr(exprs) => {
+                let origin = context
+                    .param_origins
+                    .get("from")
+                    .map(|(file, _)| (file.as_ref(), *span));
+                evaluated = context.evaluate(exprs, Some(name), key, None, origin)?;
+                &evaluated
+            }
+            _ => value,
+        };
+        match (key.as_str(), value) {
+            ("file", PropValue::Str(filename)) => src.push(filename),
+            ("cols", &PropValue::Int(val)) => cols = val as u32,
+            ("rows", &PropValue::Int(val)) => rows = val as u32,
+            ("margin", &PropValue::Int(val)) => margin = val as u32,
+            ("padding", &PropValue::Int(val)) => padding = val as u32,
+            ("start", &PropValue::Int(val)) => start = Some(val),
+            ("end", &PropValue::Int(val)) => end = Some(val),
+            ("step", &PropValue::Int(val)) => step = val,
+            _ => {}
         }
-        let (file_ex, src_ex) = process_valobj(&own_context);
-        if let Some(file_add) = file_ex {
-            own_context.src.push(file_add);
+    }
+
+    let src_ex = match name.as_str() {
+        "batch" => SourceEx::Batch(BatchRange::new(&src, start, end, step)?),
+        "sheet" => {
+            let layout = SheetLayout {
+                cols,
+                rows,
+                margin,
+                padding,
+            };
+            layout.cell_count()?;
+            SourceEx::Sheet(layout)
         }
+        _ => SourceEx::Single,
+    };
+    Ok((src, src_ex))
+}
 
-        if own_context.params.contains_key("raw") {
-            Ok(Task {
-                name: name.clone(),
-                src: own_context.src.to_slash().unwrap().into_owned(),
-                dest: own_context.dest.to_slash().unwrap().into_owned(),
-                kind: TaskKind::CopyFile(*res_type),
-                src_ex,
-            })
-        } else {
-            let kind = match res_type {
-                ResType::Texture => {
-                    let mut tex_params = TextureParams::default();
-                    tex_params.apply(&own_context);
-                    TaskKind::TextureConvert(tex_params)
+/// `size` of the source image is only known when the task is built, see [`SizedParams`].
+fn make_task(
+    res_type: ResType,
+    name: &Option<String>,
+    src: PathBuf,
+    src_ex: SourceEx,
+    context: &TaskParams,
+    size: Option<(u32, u32)>,
+) -> anyhow::Result<Task> {
+    let evaluated;
+    let (context, sized_exprs) = if context
+        .params
+        .values()
+        .any(|value| matches!(value, PropValue::Expr(_)))
+    {
+        let sized_exprs;
+        (evaluated, sized_exprs) = context.evaluated(size)?;
+        (&evaluated, sized_exprs)
+    } else {
+        (context, Vec::new())
+    };
+
+    let kind = if context.params.contains_key("raw") {
+        TaskKind::CopyFile(res_type)
+    } else {
+        match res_type {
+            ResType::Texture => {
+                let mut tex_params = TextureParams::default();
+                tex_params.apply(context);
+                TaskKind::TextureConvert(tex_params)
+            }
+            ResType::Font => {
+                let mut font_params = FontParams::default();
+                font_params.apply(context);
+                if let SourceEx::Sheet(layout) = &src_ex {
+                    font_params.cols = layout.cols;
+                    font_params.rows = layout.rows;
                 }
-                ResType::Font => {
-                    let mut font_params = FontParams::default();
-                    font_params.apply(&own_context);
-                    TaskKind::FontConvert(font_params)
+                // Every frame of a batch is one glyph, so the sequence length decides the character range.
+                if matches!(src_ex, SourceEx::Batch(_)) && !context.params.contains_key("end_char")
+                {
+                    font_params.end_char = None;
+                    if !context.params.contains_key("fallback_char") {
+                        font_params.fallback_char = None;
+                    }
                 }
-                ResType::Sprite => {
-                    let mut sprite_params = SpriteParams::default();
-                    sprite_params.apply(&own_context);
-                    TaskKind::SpriteConvert(sprite_params)
+                TaskKind::FontConvert(font_params)
+            }
+            ResType::Sprite => {
+                let mut sprite_params = SpriteParams::default();
+                sprite_params.apply(context);
+                if let SourceEx::Sheet(layout) = &src_ex {
+                    sprite_params.cols = layout.cols;
+                    sprite_params.rows = layout.rows;
                 }
-                ResType::IntMap => TaskKind::CopyFile(ResType::IntMap),
-                ResType::ExtMap => TaskKind::CopyFile(ResType::ExtMap),
-            };
-            Ok(Task {
-                name: name.clone(),
-                src: own_context.src.to_slash().unwrap().into_owned(),
-                dest: own_context.dest.to_slash().unwrap().into_owned(),
-                kind,
-                src_ex,
-            })
+                TaskKind::SpriteConvert(sprite_params)
+            }
+            ResType::IntMap => TaskKind::CopyFile(ResType::IntMap),
+            ResType::ExtMap => TaskKind::CopyFile(ResType::ExtMap),
         }
-    } else {
-        Err(anyhow!("Node is ont an object"))
+    };
+
+    if matches!(src_ex, SourceEx::Batch(_))
+        && !matches!(kind, TaskKind::FontConvert(_) | TaskKind::SpriteConvert(_))
+    {
+        return Err(anyhow!(
+            "batch(...) sources can only be used by fonts and sprites"
+        ));
+    }
+    if matches!(src_ex, SourceEx::Sheet(_)) && matches!(kind, TaskKind::CopyFile(_)) {
+        return Err(anyhow!("sheet(...) sources can't be copied raw"));
     }
+
+    Ok(Task {
+        name: name.clone(),
+        src: src.to_slash().unwrap().into_owned(),
+        dest: context.dest.to_slash().unwrap().into_owned(),
+        kind,
+        src_ex,
+        sized: (!sized_exprs.is_empty()).then(|| SizedParams {
+            res_type,
+            context: context.clone(),
+            exprs: sized_exprs,
+        }),
+    })
+}
+
+/// Files matching the source pattern of a `*` object, each with the object name it gets from the file stem.
+fn expand_wildcard(
+    pattern: &Path,
+    context: &TaskParams,
+) -> anyhow::Result<Vec<(Option<String>, PathBuf)>> {
+    let prefix = match context.params.get("prefix") {
+        Some(PropValue::Str(prefix)) => prefix.as_str(),
+        _ => "",
+    };
+    let suffix = match context.params.get("suffix") {
+        Some(PropValue::Str(suffix)) => suffix.as_str(),
+        _ => "",
+    };
+    let case = match context.params.get("case") {
+        Some(&PropValue::Const(case)) => case,
+        _ => PropConst::None,
+    };
+
+    // Only the file name is a pattern, `[`, `?` or `*` in the directories above it are taken literally.
+    let name = pattern.file_name().unwrap_or_default().to_string_lossy();
+    let glob_pattern = match pattern.parent().and_then(|dir| dir.to_slash()) {
+        Some(dir) if !dir.is_empty() => format!("{}/{}", glob::Pattern::escape(&dir), name),
+        _ => name.into_owned(),
+    };
+    let pattern = pattern.to_slash().unwrap().into_owned();
+    let mut result = Vec::new();
+    for entry in glob::glob(&glob_pattern)? {
+        let path = entry?;
+        if !path.is_file() {
+            continue;
+        }
+        let Some(stem) = path.file_stem() else {
+            continue;
+        };
+        let stem = stem.to_string_lossy();
+        let stem = match case {
+            PropConst::Lower => stem.to_lowercase(),
+            PropConst::Upper => stem.to_uppercase(),
+            _ => stem.into_owned(),
+        };
+        result.push((Some(format!("{}{}{}", prefix, stem, suffix)), path));
+    }
+
+    if result.is_empty() {
+        return Err(anyhow!("Pattern \"{}\" doesn't match any file", pattern));
+    }
+    Ok(result)
+}
+
+/// Turns a texture sheet into one task per cell, named by the sheet `name` pattern (`<object>_%d` by default).
+fn expand_sheet(
+    task: Task,
+    layout: SheetLayout,
+    context: &TaskParams,
+) -> anyhow::Result<Vec<Task>> {
+    let custom_pattern = match context.params.get("from") {
+        Some(PropValue::ValObj(_, props)) => {
+            props
+                .iter()
+                .find_map(|(key, value, _)| match (key.as_str(), value) {
+                    ("name", PropValue::Str(pattern)) => Some(pattern.clone()),
+                    _ => None,
+                })
+        }
+        _ => None,
+    };
+    let pattern = match custom_pattern {
+        Some(pattern) => NumberPattern::parse(&pattern)?,
+        None => {
+            let entry_path = task.entry_path()?;
+            let base = entry_path.rsplit('/').next().unwrap_or_default();
+            NumberPattern::numbered(&format!("{}_", base))
+        }
+    };
+
+    Ok((0..layout.cell_count()?)
+        .map(|index| Task {
+            name: Some(pattern.format(index)),
+            src: task.src.clone(),
+            dest: task.dest.clone(),
+            kind: task.kind.clone(),
+            src_ex: SourceEx::SheetCell(layout.clone(), index),
+            sized: task.sized.clone(),
+        })
+        .collect())
 }
 
-pub fn generate_project(root: &Node) -> anyhow::Result<PackageTask> {
-    if let Node::Package(filename, props, childs) = root {
+/// `profile` selects the `profile` blocks and property overrides that apply, none of them do without it.
+pub fn generate_project(
+    root: &Node,
+    source_file: &Path,
+    source: String,
+    overrides: HashMap<String, String>,
+    profile: Option<&str>,
+) -> anyhow::Result<PackageTask> {
+    if let Node::Package(filename, props, childs, ..) = root {
         let mut result = PackageTask {
             filename: filename.clone(),
             tasks: Vec::new(),
+            project_files: vec![source_file.to_path_buf()],
+            profiles: HashSet::new(),
         };
+        collect_profiles(root, &mut result.profiles);
 
-        let mut params = TaskParams::new();
+        let mut params = TaskParams::new(base_dir(source_file));
+        params.includes.push(source_file.canonicalize()?);
+        params.file = Arc::new(SourceFile {
+            path: source_file.to_path_buf(),
+            text: source,
+        });
+        params.defines = Defines::new(overrides);
+        params.profile = profile.map(str::to_string);
+        params.enter_package(childs)?;
         if let Some(someprops) = props {
-            params.append_props(someprops, None);
+            params.append_props(someprops, None)?;
         }
 
         for node in childs {
-            process_node(node, &mut result, &params);
+            process_node(node, &mut result, &params)?;
+        }
+        // A project without profiles builds the same for every profile, so build scripts can pass
+        // `--profile` to all packages. With profiles, a name none of them has is most likely a typo.
+        if let Some(profile) = profile
+            && !result.profiles.is_empty()
+            && !result.profiles.contains(profile)
+        {
+            let mut known: Vec<&str> = result.profiles.iter().map(String::as_str).collect();
+            known.sort();
+            return Err(anyhow!(
+                "Unknown profile \"{}\", the project has {}",
+                profile,
+                known.join(", ")
+            ));
         }
+        result.tasks = dedup_tasks(result.tasks)?;
 
         Ok(result)
     } else {
@@ -482,11 +1055,579 @@ pub fn generate_project(root: &Node) -> anyhow::Result<PackageTask> {
     }
 }
 
-pub fn generate_task(root: &Node) -> anyhow::Result<Task> {
-    if matches!(root, Node::Object(_, _, _)) {
-        let params = TaskParams::new();
-        process_object(root, &params)
-    } else {
-        Err(anyhow!("Root is not a task"))
+/// Params an object ends up with after inheriting from its package, folders and presets.
+pub struct ObjectParams {
+    pub res_type: ResType,
+    pub name: Option<String>,
+    pub src: PathBuf,
+    pub dest: String,
+    /// Every other property, sorted by key.
+    pub params: Vec<(String, PropValue)>,
+}
+
+/// Resolves the params of the object declared last before `pos`, for editor hovers.
+/// `${NAME}` only sees the package defines and environment variables here.
+pub fn object_params_at(
+    root: &Node,
+    source_file: &Path,
+    pos: Span,
+) -> anyhow::Result<Option<ObjectParams>> {
+    let Some((node, mut context)) = node_at(root, source_file, pos)? else {
+        return Ok(None);
+    };
+    let (res_type, name) = match node {
+        Node::Object(res_type, name, props, _) => {
+            if let Some(someprops) = props {
+                context.append_props(someprops, None)?;
+            }
+            (*res_type, name.clone())
+        }
+        Node::ObjectImport(res_type, name, path, _) => {
+            let path = &context.defines.expand(path)?;
+            let (name, _) = import_object(*res_type, name, path, &mut context)?;
+            (*res_type, name)
+        }
+        _ => return Ok(None),
+    };
+
+    let mut params: Vec<(String, PropValue)> = context.params.into_iter().collect();
+    params.sort_by(|(key1, _), (key2, _)| key1.cmp(key2));
+    Ok(Some(ObjectParams {
+        res_type,
+        name,
+        src: context.src,
+        dest: context.dest.to_slash().unwrap().into_owned(),
+        params,
+    }))
+}
+
+/// File an `import` or `include` written on the line of `pos` refers to, for editor go-to-definition.
+pub fn referenced_file_at(
+    root: &Node,
+    source_file: &Path,
+    pos: Span,
+) -> anyhow::Result<Option<PathBuf>> {
+    let Some((node, context)) = node_at(root, source_file, pos)? else {
+        return Ok(None);
+    };
+    match node {
+        Node::ObjectImport(.., path, span) | Node::Include(path, span) if span.line == pos.line => {
+            Ok(Some(context.origin.join(context.defines.expand(path)?)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Node declared last before `pos`, with the context its own props get applied to.
+fn node_at<'a>(
+    root: &'a Node,
+    source_file: &Path,
+    pos: Span,
+) -> anyhow::Result<Option<(&'a Node, TaskParams)>> {
+    let mut context = TaskParams::new(base_dir(source_file));
+    // Hovers show expressions as written, nothing is evaluated that could need the text for a diagnostic.
+    context.file = Arc::new(SourceFile {
+        path: source_file.to_path_buf(),
+        text: String::new(),
+    });
+    let mut found = None;
+    find_node(root, &context, pos, &mut found)?;
+    Ok(found)
+}
+
+fn find_node<'a>(
+    node: &'a Node,
+    context: &TaskParams,
+    pos: Span,
+    found: &mut Option<(&'a Node, TaskParams)>,
+) -> anyhow::Result<()> {
+    let start = node.span();
+    if (start.line, start.col) > (pos.line, pos.col) {
+        return Ok(());
+    }
+    *found = Some((node, context.clone()));
+
+    let mut own_context = context.clone();
+    let (props, childs) = match node {
+        Node::Package(_, props, childs, ..) => {
+            own_context.enter_package(childs)?;
+            (props, childs)
+        }
+        Node::Folder(path, props, childs, ..) => {
+            own_context.dest.push(own_context.defines.expand(path)?);
+            (props, childs)
+        }
+        Node::Profile(_, childs, ..) => (&None, childs),
+        _ => return Ok(()),
+    };
+    if let Some(someprops) = props {
+        own_context.append_props(someprops, None)?;
+    }
+    for child in childs {
+        find_node(child, &own_context, pos, found)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        build::{BuildOptions, Verbosity, build_package},
+        project::project_from_file,
+        testing::TempDir,
+    };
+
+    /// Resolves `source` written to a project file in a directory of its own.
+    fn project(name: &str, source: &str) -> anyhow::Result<PackageTask> {
+        project_in_profile(name, source, None)
+    }
+
+    fn project_in_profile(
+        name: &str,
+        source: &str,
+        profile: Option<&str>,
+    ) -> anyhow::Result<PackageTask> {
+        let dir = TempDir::new(&format!("tasks-{}", name));
+        let file = dir.write("test.pnd", source);
+        project_from_file(&file, HashMap::new(), profile)
+    }
+
+    /// Entry paths of the tasks with the dithering of their textures.
+    fn dithering(package: &PackageTask) -> Vec<(String, DitheringMethod)> {
+        package
+            .tasks
+            .iter()
+            .map(|task| {
+                let TaskKind::TextureConvert(params) = &task.kind else {
+                    panic!("{:?} is not a texture", task.kind);
+                };
+                (task.entry_path().unwrap(), params.dithering)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn size_is_read_when_built() {
+        let package = project(
+            "sized",
+            "package p {\n    define HALF \"2\"\n    sprite coin (from \"missing.png\", origin w / HALF h, fps 10)\n}\n",
+        )
+        .unwrap();
+        let task = &package.tasks[0];
+        let Some(sized) = &task.sized else {
+            panic!("origin isn't left for the build");
+        };
+        assert_eq!(
+            sized.exprs,
+            [(String::from("origin"), String::from("w / HALF h, HALF 2"))]
+        );
+        let TaskKind::SpriteConvert(params) = &task.kind else {
+            panic!("{:?} is not a sprite", task.kind);
+        };
+        assert_eq!((params.origin_x, params.origin_y), (0, 0));
+        assert_eq!(params.frame_time, 0.1);
+
+        let err = task.with_source_size().unwrap_err();
+        assert_eq!(err.to_string(), "Can't evaluate \"origin\"");
+    }
+
+    #[test]
+    fn defines_are_checked_before_the_build() {
+        let err = project(
+            "undefined",
+            "package p {\n    sprite coin (from \"missing.png\", origin w / HALF h)\n}\n",
+        )
+        .unwrap_err();
+        let diagnostic = err.downcast_ref::<Diagnostic>().unwrap();
+        assert_eq!(diagnostic.message, "undefined variable `HALF`");
+        assert_eq!((diagnostic.line, diagnostic.col), (2, 49));
+    }
+
+    #[test]
+    fn first_bad_param_in_key_order() {
+        for _ in 0..8 {
+            let err = project(
+                "order",
+                "package p {\n    sprite coin (fps 1 / 0, origin 1 / 0 0, cols 1 / 0)\n}\n",
+            )
+            .unwrap_err();
+            let diagnostic = err.downcast_ref::<Diagnostic>().unwrap();
+            assert_eq!(diagnostic.message, "division by zero");
+            // `cols` comes first.
+            assert_eq!((diagnostic.line, diagnostic.col), (2, 52));
+            assert!(diagnostic.to_string().contains("cols 1 / 0"));
+        }
+    }
+
+    /// Project importing `sub/logo.prj` from a folder with a `from` of its own, and a 4x4 `sub/logo.png`.
+    fn import_project(dir: &TempDir, import: &str) -> PackageTask {
+        dir.write("sub/logo.prj", import);
+        image::RgbaImage::new(4, 4)
+            .save(dir.path().join("sub/logo.png"))
+            .unwrap();
+        let file = dir.write(
+            "test.pnd",
+            "package p {\n    \"/ui\" (from \"elsewhere\") {\n        tex logo import \"sub/logo.prj\"\n    }\n}\n",
+        );
+        project_from_file(&file, HashMap::new(), None).unwrap()
+    }
+
+    #[test]
+    fn import_resolves_against_the_imported_file() {
+        let dir = TempDir::new("tasks-import");
+        let package = import_project(&dir, "tex (from \"logo.png\", dither fs)");
+        let task = &package.tasks[0];
+        assert_eq!(
+            Path::new(&task.src),
+            dir.path().join("sub").join("logo.png")
+        );
+        assert_eq!(task.entry_path().unwrap(), "/ui/logo");
+
+        let options = BuildOptions {
+            force: true,
+            jobs: Some(1),
+            verbosity: Verbosity::Quiet,
+        };
+        build_package(&package, dir.path().join("build"), &options).unwrap();
+    }
+
+    #[test]
+    fn imported_name_wins() {
+        let dir = TempDir::new("tasks-import-name");
+        let package = import_project(&dir, "tex icon (from \"logo.png\")");
+        assert_eq!(package.tasks[0].entry_path().unwrap(), "/ui/icon");
+    }
+
+    const PROFILES: &str = r#"package p (dither fs, profile dev (dither none)) {
+    tex logo (from "logo.png", profile release (dither ord8))
+    profile dev {
+        tex debug (from "debug.png")
+    }
+}
+"#;
+
+    #[test]
+    fn profile_blocks_and_overrides() {
+        assert_eq!(
+            dithering(&project_in_profile("profile-none", PROFILES, None).unwrap()),
+            [(String::from("/logo"), DitheringMethod::FS)]
+        );
+        assert_eq!(
+            dithering(&project_in_profile("profile-dev", PROFILES, Some("dev")).unwrap()),
+            [
+                (String::from("/logo"), DitheringMethod::No),
+                (String::from("/debug"), DitheringMethod::No)
+            ]
+        );
+        assert_eq!(
+            dithering(&project_in_profile("profile-release", PROFILES, Some("release")).unwrap()),
+            [(String::from("/logo"), DitheringMethod::Ord8)]
+        );
+    }
+
+    #[test]
+    fn unknown_profile() {
+        let err = project_in_profile("profile-typo", PROFILES, Some("relase")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown profile \"relase\", the project has dev, release"
+        );
+    }
+
+    #[test]
+    fn any_profile_without_profiles() {
+        let package = project_in_profile(
+            "profile-less",
+            "package p {\n    tex logo (from \"logo.png\", dither ord4)\n}\n",
+            Some("release"),
+        )
+        .unwrap();
+        assert_eq!(
+            dithering(&package),
+            [(String::from("/logo"), DitheringMethod::Ord4)]
+        );
+    }
+
+    #[test]
+    fn wildcard_names() {
+        let dir = TempDir::new("tasks-wildcard");
+        for name in ["Floor_Big.png", "floor1.png", "wall.tex", "dir.png/keep"] {
+            dir.write(&format!("floors/{}", name), "");
+        }
+        let file = dir.write(
+            "test.pnd",
+            r#"package p {
+    tex * (from "floors/*.png")
+    "/lower" {
+        tex * (from "floors/*.png", prefix "f_", suffix "_x", case lower)
+    }
+    "/upper" {
+        tex * (from "floors/floor?.png", case upper)
+    }
+}
+"#,
+        );
+        let package = project_from_file(&file, HashMap::new(), None).unwrap();
+        let entries: Vec<String> = package
+            .tasks
+            .iter()
+            .map(|task| task.entry_path().unwrap())
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                "/Floor_Big",
+                "/floor1",
+                "/lower/f_floor_big_x",
+                "/lower/f_floor1_x",
+                "/upper/FLOOR1"
+            ]
+        );
+        assert_eq!(
+            Path::new(&package.tasks[0].src),
+            dir.path().join("floors/Floor_Big.png")
+        );
+    }
+
+    #[test]
+    fn wildcard_in_special_directory() {
+        let dir = TempDir::new("tasks-wildcard [x]?");
+        dir.write("a [1]/tile.png", "");
+        dir.write("a 1/other.png", "");
+        let file = dir.write(
+            "test.pnd",
+            "package p {\n    tex * (from \"a [1]/*.png\")\n}\n",
+        );
+        let package = project_from_file(&file, HashMap::new(), None).unwrap();
+        assert_eq!(package.tasks.len(), 1);
+        assert_eq!(package.tasks[0].entry_path().unwrap(), "/tile");
+    }
+
+    #[test]
+    fn wildcard_without_match() {
+        let err = project(
+            "wildcard-none",
+            "package p {\n    tex * (from \"floors/*.png\")\n}\n",
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .ends_with("floors/*.png\" doesn't match any file"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn sheet_cell_names() {
+        let package = project(
+            "sheet-names",
+            r#"package p {
+    tex tiles (from sheet(file "tiles.png", cols 2, rows 2))
+    "/ui" {
+        tex buttons (from sheet(file "buttons.png", cols 3, rows 1, name "button%02d"))
+    }
+}
+"#,
+        )
+        .unwrap();
+        let entries: Vec<String> = package
+            .tasks
+            .iter()
+            .map(|task| task.entry_path().unwrap())
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                "/tiles_0",
+                "/tiles_1",
+                "/tiles_2",
+                "/tiles_3",
+                "/ui/button00",
+                "/ui/button01",
+                "/ui/button02"
+            ]
+        );
+        assert!(matches!(
+            package.tasks[6].src_ex,
+            SourceEx::SheetCell(SheetLayout { cols: 3, .. }, 2)
+        ));
+    }
+
+    #[test]
+    fn presets_apply_before_own_props() {
+        let package = project(
+            "presets",
+            r#"package p {
+    preset soft (dither fs)
+    preset sharp (use soft, dither ord8)
+    tex a (from "a.png", use soft)
+    tex b (from "b.png", use sharp)
+    tex c (from "c.png", dither none, use sharp)
+}
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            dithering(&package),
+            [
+                (String::from("/a"), DitheringMethod::FS),
+                (String::from("/b"), DitheringMethod::Ord8),
+                (String::from("/c"), DitheringMethod::No)
+            ]
+        );
+    }
+
+    #[test]
+    fn preset_errors() {
+        let err = project(
+            "preset-cycle",
+            "package p {\n    preset a (use b)\n    preset b (use a)\n    tex x (from \"x.png\", use a)\n}\n",
+        )
+        .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("Preset \"a\" uses itself"),
+            "{:#}",
+            err
+        );
+
+        let err = project(
+            "preset-unknown",
+            "package p {\n    tex x (from \"x.png\", use missing)\n}\n",
+        )
+        .unwrap_err();
+        // Caught by validation, at the name of the preset.
+        let err = format!("{:#}", err);
+        assert!(err.contains("unknown preset `missing`"), "{}", err);
+        assert!(err.contains("test.pnd:2:30"), "{}", err);
+    }
+
+    const DEFINES: &str = r#"package p {
+    define DIR "art"
+    define LOGO "${DIR}/logo.png"
+    tex logo (from "${LOGO}")
+}
+"#;
+
+    #[test]
+    fn defines_and_overrides() {
+        let dir = TempDir::new("tasks-defines");
+        let file = dir.write("test.pnd", DEFINES);
+        let package = project_from_file(&file, HashMap::new(), None).unwrap();
+        assert_eq!(
+            Path::new(&package.tasks[0].src),
+            dir.path().join("art/logo.png")
+        );
+
+        let overrides = HashMap::from([(String::from("DIR"), String::from("gfx"))]);
+        let package = project_from_file(&file, overrides, None).unwrap();
+        assert_eq!(
+            Path::new(&package.tasks[0].src),
+            dir.path().join("gfx/logo.png")
+        );
+
+        let overrides = HashMap::from([(String::from("LOGO"), String::from("icon.png"))]);
+        let package = project_from_file(&file, overrides, None).unwrap();
+        assert_eq!(package.tasks[0].entry_path().unwrap(), "/logo");
+        assert_eq!(
+            Path::new(&package.tasks[0].src),
+            dir.path().join("icon.png")
+        );
+    }
+
+    #[test]
+    fn includes_resolve_relative_and_dedup() {
+        let dir = TempDir::new("tasks-include");
+        dir.write(
+            "common.pnd",
+            "package common {\n    tex logo (from \"logo.png\")\n}\n",
+        );
+        dir.write(
+            "ui/ui.pnd",
+            "package ui {\n    include \"../common.pnd\"\n    tex button (from \"button.png\")\n}\n",
+        );
+        let file = dir.write(
+            "test.pnd",
+            "package p {\n    include \"ui/ui.pnd\"\n    include \"common.pnd\"\n    tex logo (from \"logo.png\")\n}\n",
+        );
+        let package = project_from_file(&file, HashMap::new(), None).unwrap();
+        let sources: Vec<(String, PathBuf)> = package
+            .tasks
+            .iter()
+            .map(|task| (task.entry_path().unwrap(), PathBuf::from(&task.src)))
+            .collect();
+        assert_eq!(
+            sources,
+            [
+                (String::from("/logo"), dir.path().join("logo.png")),
+                (String::from("/button"), dir.path().join("ui/button.png"))
+            ]
+        );
+        assert!(
+            package
+                .project_files
+                .contains(&dir.path().join("ui/ui.pnd"))
+        );
+    }
+
+    #[test]
+    fn include_errors() {
+        let dir = TempDir::new("tasks-include-errors");
+        dir.write("a.pnd", "package a {\n    include \"b.pnd\"\n}\n");
+        dir.write("b.pnd", "package b {\n    include \"a.pnd\"\n}\n");
+        dir.write("logo.prj", "tex (from \"logo.png\")\n");
+        dir.write(
+            "other.pnd",
+            "package other {\n    tex logo (from \"other.png\")\n}\n",
+        );
+        let error = |items: &str| {
+            let file = dir.write("test.pnd", format!("package p {{\n{}}}\n", items));
+            format!(
+                "{:#}",
+                project_from_file(&file, HashMap::new(), None).unwrap_err()
+            )
+        };
+
+        let err = error("    include \"a.pnd\"\n");
+        assert!(
+            err.contains("Include cycle: \"a.pnd\" includes itself"),
+            "{}",
+            err
+        );
+        let err = error("    include \"logo.prj\"\n");
+        assert!(
+            err.contains("Included file \"logo.prj\" is not a package"),
+            "{}",
+            err
+        );
+        let err = error("    include \"missing.pnd\"\n");
+        assert!(err.starts_with("Can't include \"missing.pnd\""), "{}", err);
+        let err = error("    include \"other.pnd\"\n    tex logo (from \"logo.png\")\n");
+        assert!(
+            err.contains("\"/logo\" is defined twice with different sources or settings"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn signed_and_fractional_sprite_params() {
+        let package = project(
+            "sprite-numbers",
+            "package p {\n    sprite hero (from \"hero.png\", origin -16 -32, fps 12.5)\n    sprite coin (from \"coin.png\", fps 4)\n}\n",
+        )
+        .unwrap();
+        let params: Vec<_> = package
+            .tasks
+            .iter()
+            .map(|task| match &task.kind {
+                TaskKind::SpriteConvert(params) => {
+                    (params.origin_x, params.origin_y, params.frame_time)
+                }
+                kind => panic!("{:?} is not a sprite", kind),
+            })
+            .collect();
+        assert_eq!(params, [(-16, -32, 0.08), (0, 0, 0.25)]);
     }
 }
@@ -0,0 +1,672 @@
+use std::path::Path;
+
+use shared::ResType;
+
+use crate::project::{
+    ast::{Expr, Node, PropConst, PropValue, Props, Span},
+    diagnostics::{Diagnostic, Severity},
+};
+
+/// Kind of value a property accepts.
+#[derive(Debug, Clone, Copy)]
+enum Shape {
+    Flag,
+    Str,
+    /// Any integer, negative values included.
+    Int,
+    /// Integer that can't be negative, like sizes and character codes.
+    Count,
+    /// Integer or decimal fraction.
+    Number,
+    Int2,
+    Borders,
+    Dither,
+    Case,
+    Source,
+}
+
+impl Shape {
+    fn accepts(self, value: &PropValue) -> bool {
+        match self {
+            Shape::Flag => matches!(value, PropValue::Empty),
+            Shape::Str => matches!(value, PropValue::Str(_)),
+            // Expressions are checked again once they are evaluated.
+            Shape::Int | Shape::Count | Shape::Number if is_expr(value, &[1]) => true,
+            Shape::Int => matches!(value, PropValue::Int(_)),
+            Shape::Count => matches!(value, PropValue::Int(v) if *v >= 0),
+            Shape::Number => matches!(value, PropValue::Int(_) | PropValue::Float(_)),
+            Shape::Int2 => matches!(value, PropValue::Int2(..)) || is_expr(value, &[2]),
+            Shape::Borders if is_expr(value, &[1, 2, 4]) => true,
+            Shape::Borders => match value {
+                PropValue::Const(PropConst::Auto) => true,
+                &PropValue::Int(v) => v >= 0,
+                &PropValue::Int2(v1, v2) => v1 >= 0 && v2 >= 0,
+                &PropValue::Int4(v1, v2, v3, v4) => [v1, v2, v3, v4].iter().all(|&v| v >= 0),
+                _ => false,
+            },
+            Shape::Dither => matches!(
+                value,
+                PropValue::Const(
+                    PropConst::None | PropConst::Fs | PropConst::Ord4 | PropConst::Ord8
+                )
+            ),
+            Shape::Case => matches!(
+                value,
+                PropValue::Const(PropConst::None | PropConst::Lower | PropConst::Upper)
+            ),
+            Shape::Source => matches!(value, PropValue::Str(_) | PropValue::ValObj(..)),
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Shape::Flag => "no value",
+            Shape::Str => "a string",
+            Shape::Int => "an integer",
+            Shape::Count => "a non-negative integer",
+            Shape::Number => "a number",
+            Shape::Int2 => "two integers",
+            Shape::Borders => "`auto` or 1, 2 or 4 non-negative integers",
+            Shape::Dither => "one of `none`, `fs`, `ord4`, `ord8`",
+            Shape::Case => "one of `none`, `lower`, `upper`",
+            Shape::Source => "a path, `batch(...)` or `sheet(...)`",
+        }
+    }
+
+    /// Words the shape accepts as a value.
+    fn names(self) -> &'static [&'static str] {
+        match self {
+            Shape::Borders => &["auto"],
+            Shape::Dither => &["none", "fs", "ord4", "ord8"],
+            Shape::Case => &["none", "lower", "upper"],
+            Shape::Source => &["batch", "sheet"],
+            _ => &[],
+        }
+    }
+}
+
+/// Expressions for as many numbers as one of `lengths`.
+fn is_expr(value: &PropValue, lengths: &[usize]) -> bool {
+    matches!(value, PropValue::Expr(exprs) if lengths.contains(&exprs.len()))
+}
+
+const ALL_TYPES: &[ResType] = &[
+    ResType::Texture,
+    ResType::Font,
+    ResType::Sprite,
+    ResType::IntMap,
+    ResType::ExtMap,
+];
+const IMAGE_TYPES: &[ResType] = &[ResType::Texture, ResType::Font, ResType::Sprite];
+const GRID_TYPES: &[ResType] = &[ResType::Font, ResType::Sprite];
+
+/// Known properties, the kind of value they take and the object types that use them.
+/// Other object types silently ignore them.
+const PROPERTIES: &[(&str, Shape, &[ResType])] = &[
+    ("from", Shape::Source, ALL_TYPES),
+    ("raw", Shape::Flag, ALL_TYPES),
+    ("prefix", Shape::Str, ALL_TYPES),
+    ("suffix", Shape::Str, ALL_TYPES),
+    ("case", Shape::Case, ALL_TYPES),
+    ("transparent", Shape::Flag, IMAGE_TYPES),
+    ("dither", Shape::Dither, IMAGE_TYPES),
+    ("cols", Shape::Count, GRID_TYPES),
+    ("rows", Shape::Count, GRID_TYPES),
+    ("borders", Shape::Borders, &[ResType::Font]),
+    ("start_char", Shape::Count, &[ResType::Font]),
+    ("end_char", Shape::Count, &[ResType::Font]),
+    ("fallback_char", Shape::Count, &[ResType::Font]),
+    ("letter_space", Shape::Int, &[ResType::Font]),
+    ("line_height", Shape::Int, &[ResType::Font]),
+    ("origin", Shape::Int2, &[ResType::Sprite]),
+    ("fps", Shape::Number, &[ResType::Sprite]),
+];
+
+/// Value objects allowed in `from` and the properties each of them takes.
+const SOURCES: &[(&str, &[(&str, Shape)])] = &[
+    (
+        "batch",
+        &[
+            ("file", Shape::Str),
+            ("start", Shape::Count),
+            ("end", Shape::Count),
+            ("step", Shape::Count),
+        ],
+    ),
+    (
+        "sheet",
+        &[
+            ("file", Shape::Str),
+            ("cols", Shape::Count),
+            ("rows", Shape::Count),
+            ("margin", Shape::Count),
+            ("padding", Shape::Count),
+            ("name", Shape::Str),
+        ],
+    ),
+];
+
+/// Properties an object of `res_type` uses, or all of them for folders and packages (`None`),
+/// each with a description of the value it takes.
+pub fn property_names(res_type: Option<ResType>) -> Vec<(&'static str, &'static str)> {
+    PROPERTIES
+        .iter()
+        .filter(|(_, _, types)| res_type.is_none_or(|res_type| types.contains(&res_type)))
+        .map(|(name, shape, _)| (*name, shape.describe()))
+        .collect()
+}
+
+/// Properties of a `batch(...)` or `sheet(...)` source, each with a description of the value it takes.
+pub fn source_property_names(source: &str) -> Vec<(&'static str, &'static str)> {
+    SOURCES
+        .iter()
+        .filter(|(name, _)| *name == source)
+        .flat_map(|(_, props)| props.iter().map(|(name, shape)| (*name, shape.describe())))
+        .collect()
+}
+
+/// Constants and source names `key` accepts as its value.
+pub fn value_names(key: &str) -> &'static [&'static str] {
+    PROPERTIES
+        .iter()
+        .find(|(name, ..)| *name == key)
+        .map_or(&[], |(_, shape, _)| shape.names())
+}
+
+/// Checks a number computed from expressions the way written ones are checked, `source` names the
+/// `batch(...)` or `sheet(...)` the key belongs to. The error is a message for the key's position.
+pub fn check_value(source: Option<&str>, key: &str, value: &PropValue) -> Result<(), String> {
+    let shape = match source {
+        Some(source) => SOURCES
+            .iter()
+            .find(|(name, _)| *name == source)
+            .and_then(|(_, props)| props.iter().find(|(name, _)| *name == key))
+            .map(|(_, shape)| *shape),
+        None => PROPERTIES
+            .iter()
+            .find(|(name, ..)| *name == key)
+            .map(|(_, shape, _)| *shape),
+    };
+    match shape {
+        Some(shape) if !shape.accepts(value) => Err(format!(
+            "`{}` expects {}, got {}",
+            key,
+            shape.describe(),
+            describe_value(value)
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Property `key` takes fractions, so its expressions are computed in floating point.
+pub fn takes_fractions(key: &str) -> bool {
+    PROPERTIES
+        .iter()
+        .any(|(name, shape, _)| *name == key && matches!(shape, Shape::Number))
+}
+
+/// Checks every property in the tree, returns all problems found instead of stopping at the first one.
+pub fn validate(root: &Node, file: &Path, source: &str) -> Vec<Diagnostic> {
+    let mut validator = Validator {
+        file,
+        source,
+        presets: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+    validator.node(root);
+    validator.diagnostics
+}
+
+struct Validator<'a> {
+    file: &'a Path,
+    source: &'a str,
+    /// Presets declared at package level, usable anywhere in the same file.
+    presets: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Names declared so far in a package, each with the profile block it is in.
+#[derive(Default)]
+struct Declared<'a> {
+    presets: Vec<(&'a str, Option<&'a str>)>,
+    defines: Vec<(&'a str, Option<&'a str>)>,
+}
+
+impl Validator<'_> {
+    fn report(&mut self, span: Span, severity: Severity, message: String, help: Option<String>) {
+        let mut diagnostic = Diagnostic::new(
+            self.file,
+            self.source,
+            span.line,
+            span.col,
+            severity,
+            message,
+        );
+        if let Some(help) = help {
+            diagnostic = diagnostic.with_help(help);
+        }
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn node(&mut self, node: &Node) {
+        match node {
+            Node::Package(_, props, childs, ..) => {
+                self.declarations(childs, None, &mut Declared::default());
+                if let Some(props) = props {
+                    self.props(props, None);
+                }
+                self.package_items(childs);
+            }
+            Node::Folder(_, props, childs, ..) => {
+                if let Some(props) = props {
+                    self.props(props, None);
+                }
+                for child in childs {
+                    self.node(child);
+                }
+            }
+            Node::Profile(_, childs, ..) => {
+                for child in childs {
+                    self.node(child);
+                }
+            }
+            Node::Object(res_type, _, Some(props), _) => self.props(props, Some(*res_type)),
+            Node::Object(_, _, None, _) | Node::ObjectImport(..) | Node::Include(..) => {}
+            Node::Preset(_, _, span) => self.report(
+                *span,
+                Severity::Error,
+                String::from("presets can only be declared at package level"),
+                None,
+            ),
+            Node::Define(_, _, span) => self.report(
+                *span,
+                Severity::Error,
+                String::from("defines can only be declared at package level"),
+                None,
+            ),
+        }
+    }
+
+    /// Presets and defines of a package, also the ones in its profile blocks. A profile may declare
+    /// the same name again to override it, declaring a name twice for the same profile is an error.
+    fn declarations<'b>(
+        &mut self,
+        childs: &'b [Node],
+        profile: Option<&'b str>,
+        declared: &mut Declared<'b>,
+    ) {
+        for child in childs {
+            match child {
+                Node::Preset(name, _, span) => {
+                    if declared.presets.contains(&(name.as_str(), profile)) {
+                        self.report(
+                            *span,
+                            Severity::Error,
+                            format!("preset `{}` is defined twice", name),
+                            None,
+                        );
+                    }
+                    declared.presets.push((name, profile));
+                    if !self.presets.contains(name) {
+                        self.presets.push(name.clone());
+                    }
+                }
+                Node::Define(name, _, span) => {
+                    if declared.defines.contains(&(name.as_str(), profile)) {
+                        self.report(
+                            *span,
+                            Severity::Error,
+                            format!("`{}` is defined twice", name),
+                            None,
+                        );
+                    }
+                    declared.defines.push((name, profile));
+                }
+                Node::Profile(profile_name, items, ..) => {
+                    self.declarations(items, Some(profile_name), declared)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Items of a package, presets and defines are allowed here and in the profile blocks at this level.
+    fn package_items(&mut self, childs: &[Node]) {
+        for child in childs {
+            match child {
+                Node::Preset(_, preset_props, _) => self.props(preset_props, None),
+                Node::Define(..) => {}
+                Node::Profile(_, items, ..) => self.package_items(items),
+                _ => self.node(child),
+            }
+        }
+    }
+
+    /// Folder and package props (`res_type` is `None`) are inherited by any object, so only unknown keys fail there.
+    fn props(&mut self, props: &Props, res_type: Option<ResType>) {
+        for (key, value, span) in props {
+            if let ("use", PropValue::Str(name)) = (key.as_str(), value) {
+                if !self.presets.contains(name) {
+                    let help = suggest(name, self.presets.iter().map(|preset| preset.as_str()));
+                    self.report(
+                        *span,
+                        Severity::Error,
+                        format!("unknown preset `{}`", name),
+                        help,
+                    );
+                }
+                continue;
+            }
+            if let ("profile", PropValue::ValObj(_, profile_props)) = (key.as_str(), value) {
+                self.props(profile_props, res_type);
+                continue;
+            }
+            let Some(&(_, shape, types)) = PROPERTIES.iter().find(|(name, ..)| name == key) else {
+                let candidates = PROPERTIES
+                    .iter()
+                    .filter(|(_, _, types)| {
+                        res_type.is_none_or(|res_type| types.contains(&res_type))
+                    })
+                    .map(|(name, ..)| *name);
+                self.report(
+                    *span,
+                    Severity::Error,
+                    format!("unknown property `{}`", key),
+                    suggest(key, candidates),
+                );
+                continue;
+            };
+
+            self.value(key, shape, value, *span);
+            if let Some(res_type) = res_type
+                && !types.contains(&res_type)
+            {
+                self.report(
+                    *span,
+                    Severity::Warning,
+                    format!(
+                        "`{}` has no effect on `{}` objects",
+                        key,
+                        class_name(res_type)
+                    ),
+                    None,
+                );
+            }
+        }
+    }
+
+    fn value(&mut self, key: &str, shape: Shape, value: &PropValue, span: Span) {
+        if !shape.accepts(value) {
+            // The grammar reads a name right before `(` as a source, even in `origin w (h - 1)`.
+            let help = match value {
+                PropValue::ValObj(name, _) if !SOURCES.iter().any(|(source, _)| source == name) => {
+                    Some(format!(
+                        "`{0} (` starts a source, write `({0})` to use `{0}` in an expression",
+                        name
+                    ))
+                }
+                _ => None,
+            };
+            self.report(
+                span,
+                Severity::Error,
+                format!(
+                    "`{}` expects {}, got {}",
+                    key,
+                    shape.describe(),
+                    describe_value(value)
+                ),
+                help,
+            );
+            return;
+        }
+
+        let PropValue::ValObj(name, props) = value else {
+            return;
+        };
+        let Some((_, source_props)) = SOURCES.iter().find(|(source, _)| source == name) else {
+            self.report(
+                span,
+                Severity::Error,
+                format!("unknown source `{}(...)` in `{}`", name, key),
+                suggest(name, SOURCES.iter().map(|(source, _)| *source)),
+            );
+            return;
+        };
+        for (source_key, source_value, source_span) in props {
+            match source_props
+                .iter()
+                .find(|(allowed, _)| allowed == source_key)
+            {
+                Some((_, source_shape)) => {
+                    self.value(source_key, *source_shape, source_value, *source_span)
+                }
+                None => self.report(
+                    *source_span,
+                    Severity::Error,
+                    format!("unknown property `{}` in `{}(...)`", source_key, name),
+                    suggest(source_key, source_props.iter().map(|(allowed, _)| *allowed)),
+                ),
+            }
+        }
+    }
+}
+
+fn describe_value(value: &PropValue) -> String {
+    match value {
+        PropValue::Int(value) => format!("{}", value),
+        PropValue::Int2(..) => String::from("two numbers"),
+        PropValue::Int4(..) => String::from("four numbers"),
+        PropValue::Float(value) => format!("{}", value),
+        PropValue::Str(_) => String::from("a string"),
+        PropValue::Const(PropConst::Error) => String::from("an unknown name"),
+        PropValue::Const(constant) => format!("`{}`", format!("{:?}", constant).to_lowercase()),
+        PropValue::ValObj(name, _) => format!("`{}(...)`", name),
+        PropValue::Expr(exprs) => match exprs.as_slice() {
+            [Expr::Var(..)] => String::from("an unknown name"),
+            [_] => String::from("an expression"),
+            [_, _] => String::from("two numbers"),
+            _ => format!("{} numbers", exprs.len()),
+        },
+        PropValue::Empty => String::from("no value"),
+    }
+}
+
+/// Keyword declaring objects of `res_type`.
+pub fn class_name(res_type: ResType) -> &'static str {
+    match res_type {
+        ResType::Texture => "tex",
+        ResType::Font => "font",
+        ResType::Sprite => "sprite",
+        ResType::IntMap => "intmap",
+        ResType::ExtMap => "extmap",
+    }
+}
+
+/// Closest candidate within a few typos of `word`, phrased as a help message.
+fn suggest<'a>(word: &str, candidates: impl Iterator<Item = &'a str>) -> Option<String> {
+    let limit = (word.chars().count() / 3).max(1);
+    candidates
+        .map(|candidate| (edit_distance(word, candidate), candidate))
+        .filter(|&(distance, _)| distance <= limit)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, candidate)| format!("did you mean `{}`?", candidate))
+}
+
+/// Levenshtein distance, counting a swap of two neighbouring characters as one edit.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut best = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = best.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = best;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::parse_source;
+
+    /// Line, column, message and help of each problem in `source`, warnings marked as such.
+    fn problems(source: &str) -> Vec<(u32, u32, String, Option<String>)> {
+        let file = Path::new("test.pnd");
+        let tree = parse_source(file, source).unwrap();
+        validate(&tree, file, source)
+            .into_iter()
+            .map(|diagnostic| {
+                let message = match diagnostic.severity {
+                    Severity::Error => diagnostic.message,
+                    Severity::Warning => format!("warning: {}", diagnostic.message),
+                };
+                (diagnostic.line, diagnostic.col, message, diagnostic.help)
+            })
+            .collect()
+    }
+
+    fn problem(
+        line: u32,
+        col: u32,
+        message: &str,
+        help: Option<&str>,
+    ) -> (u32, u32, String, Option<String>) {
+        (line, col, message.to_string(), help.map(String::from))
+    }
+
+    #[test]
+    fn unknown_properties() {
+        assert_eq!(
+            problems("package p (frame 2) {\n    tex a (from \"a.png\", dihter fs, fps 4)\n}\n"),
+            [
+                problem(1, 12, "unknown property `frame`", None),
+                problem(
+                    2,
+                    26,
+                    "unknown property `dihter`",
+                    Some("did you mean `dither`?")
+                ),
+                problem(2, 37, "warning: `fps` has no effect on `tex` objects", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn wrong_shapes() {
+        let source = "package p {
+    font f (from \"f.png\", cols \"16\", borders 1 2 3)
+    sprite s (from \"s.png\", origin 5, dither fast)
+    sprite t (from \"t.png\", origin w (h))
+}
+";
+        assert_eq!(
+            problems(source),
+            [
+                problem(
+                    2,
+                    27,
+                    "`cols` expects a non-negative integer, got a string",
+                    None
+                ),
+                problem(
+                    2,
+                    38,
+                    "`borders` expects `auto` or 1, 2 or 4 non-negative integers, got 3 numbers",
+                    None
+                ),
+                problem(3, 29, "`origin` expects two integers, got 5", None),
+                problem(
+                    3,
+                    39,
+                    "`dither` expects one of `none`, `fs`, `ord4`, `ord8`, got an unknown name",
+                    None
+                ),
+                problem(
+                    4,
+                    29,
+                    "`origin` expects two integers, got `w(...)`",
+                    Some("`w (` starts a source, write `(w)` to use `w` in an expression")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_sources() {
+        let source = "package p {
+    sprite s (from sheet(file \"s.png\", cols 2, rwos 1))
+    tex t (from sheat(file \"t.png\"))
+}
+";
+        assert_eq!(
+            problems(source),
+            [
+                problem(
+                    2,
+                    48,
+                    "unknown property `rwos` in `sheet(...)`",
+                    Some("did you mean `rows`?")
+                ),
+                problem(
+                    3,
+                    12,
+                    "unknown source `sheat(...)` in `from`",
+                    Some("did you mean `sheet`?")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn declarations() {
+        let source = "package p {
+    preset soft (dither fs)
+    preset soft (dither ord4)
+    profile dev {
+        preset soft (dither none)
+    }
+    \"/ui\" {
+        define DIR \"ui\"
+    }
+}
+";
+        assert_eq!(
+            problems(source),
+            [
+                problem(3, 12, "preset `soft` is defined twice", None),
+                problem(8, 16, "defines can only be declared at package level", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn suggestions() {
+        let names = ["dither", "transparent", "cols"].into_iter();
+        assert_eq!(
+            suggest("transparnet", names.clone()).as_deref(),
+            Some("did you mean `transparent`?")
+        );
+        assert_eq!(
+            suggest("col", names.clone()).as_deref(),
+            Some("did you mean `cols`?")
+        );
+        assert_eq!(suggest("speed", names), None);
+        assert_eq!(edit_distance("ab", "ba"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+}
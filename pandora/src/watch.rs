@@ -0,0 +1,323 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+
+use crate::{
+    build::{BuildOptions, Verbosity, build_package, resolve_sources},
+    project::{
+        diagnostics::{Diagnostic, Diagnostics},
+        project_from_file,
+        tasks::PackageTask,
+    },
+};
+
+/// Editors often save a file in several steps, events arriving this soon after the first one join its rebuild.
+const SETTLE_TIME: Duration = Duration::from_millis(100);
+
+/// Builds the package, then again every time one of its inputs changes, until the process is interrupted.
+/// The project is parsed again on each change, unchanged tasks come from the build cache.
+/// Errors are printed and watching goes on, so a broken edit can be fixed by the next save.
+pub fn watch_package(
+    project: &Path,
+    defines: &HashMap<String, String>,
+    profile: Option<&str>,
+    out_dir: &Path,
+    options: &BuildOptions,
+) -> anyhow::Result<()> {
+    let mut watcher = Watcher::new()?;
+    let mut inputs = Inputs::default();
+    let mut build_options = BuildOptions {
+        force: options.force,
+        jobs: options.jobs,
+        // Per-task lines only in verbose mode, otherwise each build is reported by a single line.
+        verbosity: match options.verbosity {
+            Verbosity::Verbose => Verbosity::Verbose,
+            _ => Verbosity::Quiet,
+        },
+    };
+
+    loop {
+        let started = Instant::now();
+        match project_from_file(project, defines.clone(), profile) {
+            Ok(package) => {
+                inputs = Inputs::of_package(&package);
+                match build_package(&package, out_dir, &build_options) {
+                    Ok(summary) if options.verbosity >= Verbosity::Normal => println!(
+                        "{}: {} converted, {} cached in {:.2}s",
+                        summary.package_file.display(),
+                        summary.converted,
+                        summary.cached,
+                        started.elapsed().as_secs_f64()
+                    ),
+                    Ok(_) => {}
+                    Err(err) => eprintln!("Error: {:?}\n", err),
+                }
+                build_options.force = false;
+            }
+            Err(err) => {
+                // Keep watching the last good set of files, plus the ones the diagnostics point at.
+                inputs.add_diagnostic_files(&err);
+                eprintln!("Error: {:?}\n", err);
+            }
+        }
+        inputs.files.insert(normalize(project));
+
+        watcher.watch(&inputs)?;
+        if options.verbosity == Verbosity::Verbose {
+            println!(
+                "Watching {} files in {} directories",
+                inputs.files.len(),
+                watcher.dirs.len()
+            );
+        }
+        watcher.wait(&inputs)?;
+    }
+}
+
+/// Files a build depends on.
+#[derive(Default)]
+struct Inputs {
+    /// Project files and resolved task sources, any change to them triggers a rebuild.
+    files: HashSet<PathBuf>,
+    /// Directories holding task sources, files appearing or disappearing there can change a wildcard or batch.
+    source_dirs: HashSet<PathBuf>,
+}
+
+impl Inputs {
+    fn of_package(package: &PackageTask) -> Inputs {
+        let mut inputs = Inputs::default();
+        inputs
+            .files
+            .extend(package.project_files.iter().map(|file| normalize(file)));
+        for task in &package.tasks {
+            // A missing source still gets its directory watched, so creating it triggers a rebuild.
+            let sources = resolve_sources(task).unwrap_or_else(|_| vec![PathBuf::from(&task.src)]);
+            for source in sources {
+                let source = normalize(&source);
+                if let Some(dir) = source.parent() {
+                    inputs.source_dirs.insert(dir.to_path_buf());
+                }
+                inputs.files.insert(source);
+            }
+        }
+        inputs
+    }
+
+    fn add_diagnostic_files(&mut self, err: &anyhow::Error) {
+        for cause in err.chain() {
+            if let Some(diagnostic) = cause.downcast_ref::<Diagnostic>() {
+                self.files.insert(normalize(&diagnostic.file));
+            } else if let Some(Diagnostics(diagnostics)) = cause.downcast_ref::<Diagnostics>() {
+                self.files.extend(
+                    diagnostics
+                        .iter()
+                        .map(|diagnostic| normalize(&diagnostic.file)),
+                );
+            }
+        }
+    }
+
+    fn dirs(&self) -> HashSet<PathBuf> {
+        let mut dirs = self.source_dirs.clone();
+        dirs.extend(
+            self.files
+                .iter()
+                .filter_map(|file| file.parent())
+                .map(Path::to_path_buf),
+        );
+        dirs
+    }
+
+    fn is_affected(&self, dir: &Path, name: &str, mask: EventMask) -> bool {
+        let listing_changed = mask.intersects(
+            EventMask::CREATE | EventMask::DELETE | EventMask::MOVED_FROM | EventMask::MOVED_TO,
+        );
+        self.files.contains(&dir.join(name))
+            || (listing_changed && self.source_dirs.contains(dir) && !is_scratch_file(name))
+    }
+}
+
+/// Watches directories rather than files: editors that save by writing a new file and renaming it
+/// over the old one would otherwise leave a watch on the deleted file.
+struct Watcher {
+    inotify: Inotify,
+    dirs: HashMap<WatchDescriptor, PathBuf>,
+}
+
+impl Watcher {
+    fn new() -> anyhow::Result<Watcher> {
+        Ok(Watcher {
+            inotify: Inotify::init().context("Can't initialize inotify")?,
+            dirs: HashMap::new(),
+        })
+    }
+
+    /// Updates the watched directories to the ones `inputs` needs.
+    fn watch(&mut self, inputs: &Inputs) -> anyhow::Result<()> {
+        let wanted = inputs.dirs();
+        let unwanted: Vec<WatchDescriptor> = self
+            .dirs
+            .iter()
+            .filter(|(_, dir)| !wanted.contains(*dir))
+            .map(|(wd, _)| wd.clone())
+            .collect();
+        for wd in unwanted {
+            self.dirs.remove(&wd);
+            // Fails when the directory was deleted, its watch is gone already then.
+            let _ = self.inotify.watches().remove(wd);
+        }
+
+        let mask = WatchMask::CLOSE_WRITE
+            | WatchMask::CREATE
+            | WatchMask::DELETE
+            | WatchMask::MOVED_FROM
+            | WatchMask::MOVED_TO
+            | WatchMask::ONLYDIR;
+        for dir in wanted {
+            if self.dirs.values().any(|watched| *watched == dir) || !dir.is_dir() {
+                continue;
+            }
+            let wd = self
+                .inotify
+                .watches()
+                .add(&dir, mask)
+                .with_context(|| format!("Can't watch \"{}\"", dir.display()))?;
+            self.dirs.insert(wd, dir);
+        }
+        Ok(())
+    }
+
+    /// Blocks until an input changes, then waits for the changes to settle.
+    fn wait(&mut self, inputs: &Inputs) -> anyhow::Result<()> {
+        let mut buffer = [0; 4096];
+        loop {
+            let mut affected = false;
+            for event in self.inotify.read_events_blocking(&mut buffer)? {
+                if event.mask.contains(EventMask::Q_OVERFLOW) {
+                    affected = true;
+                } else if event.mask.contains(EventMask::IGNORED) {
+                    self.dirs.remove(&event.wd);
+                } else if let (Some(dir), Some(name)) = (self.dirs.get(&event.wd), event.name) {
+                    affected |= inputs.is_affected(dir, &name.to_string_lossy(), event.mask);
+                }
+            }
+            if affected {
+                break;
+            }
+        }
+
+        thread::sleep(SETTLE_TIME);
+        // Everything queued until now is covered by the coming rebuild.
+        loop {
+            match self.inotify.read_events(&mut buffer) {
+                Ok(events) => {
+                    if events.count() == 0 {
+                        return Ok(());
+                    }
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+/// Backup, swap and other temporary files editors keep next to the sources.
+fn is_scratch_file(name: &str) -> bool {
+    name.starts_with('.') || name.ends_with('~')
+}
+
+/// Absolute path with symlinks resolved, so paths from events compare equal to the ones from the project.
+fn normalize(path: &Path) -> PathBuf {
+    path.canonicalize()
+        .or_else(|_| std::path::absolute(path))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{project::ast_from_file, testing::TempDir};
+
+    /// Project with a texture, a missing source and an include, with its inputs.
+    fn inputs(dir: &TempDir) -> Inputs {
+        dir.write("art/logo.png", "");
+        dir.write(
+            "common.pnd",
+            "package common {\n    tex missing (from \"new/missing.png\")\n}\n",
+        );
+        let file = dir.write(
+            "test.pnd",
+            "package p {\n    include \"common.pnd\"\n    tex logo (from \"art/logo.png\")\n}\n",
+        );
+        Inputs::of_package(&project_from_file(&file, HashMap::new(), None).unwrap())
+    }
+
+    #[test]
+    fn project_files_and_sources() {
+        let dir = TempDir::new("watch-inputs");
+        let inputs = inputs(&dir);
+        let root = normalize(dir.path());
+        let files: HashSet<PathBuf> = ["test.pnd", "common.pnd", "art/logo.png", "new/missing.png"]
+            .iter()
+            .map(|name| root.join(name))
+            .collect();
+        assert_eq!(inputs.files, files);
+        assert_eq!(
+            inputs.source_dirs,
+            HashSet::from([root.join("art"), root.join("new")])
+        );
+        assert_eq!(
+            inputs.dirs(),
+            HashSet::from([root.clone(), root.join("art"), root.join("new")])
+        );
+    }
+
+    #[test]
+    fn affected_by() {
+        let dir = TempDir::new("watch-affected");
+        let inputs = inputs(&dir);
+        let root = normalize(dir.path());
+        let art = root.join("art");
+        assert!(inputs.is_affected(&root, "common.pnd", EventMask::CLOSE_WRITE));
+        assert!(inputs.is_affected(&art, "logo.png", EventMask::MOVED_TO));
+        assert!(!inputs.is_affected(&root, "notes.txt", EventMask::CLOSE_WRITE));
+        // New files in a source directory can change what a wildcard or batch matches.
+        assert!(inputs.is_affected(&art, "wall.png", EventMask::CREATE));
+        assert!(!inputs.is_affected(&art, "wall.png", EventMask::CLOSE_WRITE));
+        assert!(!inputs.is_affected(&art, ".logo.png.swp", EventMask::CREATE));
+        assert!(!inputs.is_affected(&art, "logo.png~", EventMask::DELETE));
+        assert!(!inputs.is_affected(&root, "other.png", EventMask::CREATE));
+    }
+
+    #[test]
+    fn broken_project_keeps_its_file() {
+        let dir = TempDir::new("watch-broken");
+        let file = dir.write("test.pnd", "package p {\n    tex a (from \"a.png\"\n}\n");
+        let err = ast_from_file(&file).unwrap_err();
+        let mut inputs = Inputs::default();
+        inputs.add_diagnostic_files(&err);
+        assert_eq!(inputs.files, HashSet::from([normalize(&file)]));
+    }
+
+    #[test]
+    fn wakes_up_on_change() {
+        let dir = TempDir::new("watch-wait");
+        let inputs = inputs(&dir);
+        let mut watcher = Watcher::new().unwrap();
+        watcher.watch(&inputs).unwrap();
+        // `new` doesn't exist yet, so it isn't watched.
+        assert_eq!(watcher.dirs.len(), 2);
+        fs::write(dir.path().join("art/logo.png"), "changed").unwrap();
+        watcher.wait(&inputs).unwrap();
+    }
+}
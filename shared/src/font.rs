@@ -0,0 +1,107 @@
+use crate::{
+    package::{ByteReader, PackageError},
+    texture::Texture,
+};
+
+/// Bitmap font as written by pandora, glyph metrics followed by the RGB565 glyph sheet.
+///
+/// ```text
+/// start_char: u32, glyph_count: u32, fallback_char: u32, letter_space: i32, line_height: i32,
+/// glyphs: glyph_count times x: u16, y: u16, width: u16, height: u16
+/// sheet: texture
+/// ```
+#[derive(Debug, Clone)]
+pub struct Font {
+    pub start_char: u32,
+    pub fallback_char: u32,
+    /// Extra pixels between neighbouring glyphs, can be negative.
+    pub letter_space: i32,
+    pub line_height: i32,
+    pub glyphs: Vec<Glyph>,
+    pub sheet: Texture,
+}
+
+/// Source rectangle of a character on the glyph sheet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Glyph {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Font {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Font, PackageError> {
+        let mut reader = ByteReader::new(bytes);
+        let start_char = reader.u32()?;
+        let glyph_count = reader.u32()?;
+        let fallback_char = reader.u32()?;
+        let letter_space = reader.i32()?;
+        let line_height = reader.i32()?;
+
+        let mut glyphs = Vec::with_capacity((glyph_count as usize).min(reader.remaining() / 8));
+        for _ in 0..glyph_count {
+            glyphs.push(Glyph {
+                x: reader.u16()?,
+                y: reader.u16()?,
+                width: reader.u16()?,
+                height: reader.u16()?,
+            });
+        }
+        let sheet = Texture::read(&mut reader)?;
+        if !reader.is_empty() {
+            return Err(PackageError::BadEntry(String::from(
+                "unexpected data after font sheet",
+            )));
+        }
+
+        if fallback_char
+            .checked_sub(start_char)
+            .is_none_or(|index| index >= glyph_count)
+        {
+            return Err(PackageError::BadEntry(format!(
+                "fallback character {} is not in the font",
+                fallback_char
+            )));
+        }
+        for glyph in &glyphs {
+            if glyph.x as u32 + glyph.width as u32 > sheet.width
+                || glyph.y as u32 + glyph.height as u32 > sheet.height
+            {
+                return Err(PackageError::BadEntry(String::from(
+                    "glyph is outside of the font sheet",
+                )));
+            }
+        }
+
+        Ok(Font {
+            start_char,
+            fallback_char,
+            letter_space,
+            line_height,
+            glyphs,
+            sheet,
+        })
+    }
+
+    /// Metrics for `ch`, characters the font doesn't have use the fallback glyph.
+    pub fn glyph(&self, ch: char) -> &Glyph {
+        let index = (ch as u32)
+            .checked_sub(self.start_char)
+            .filter(|&index| (index as usize) < self.glyphs.len())
+            .unwrap_or(self.fallback_char - self.start_char);
+        &self.glyphs[index as usize]
+    }
+
+    /// Width of a single line of text in pixels, including the spacing between glyphs.
+    pub fn text_width(&self, text: &str) -> i32 {
+        let mut width = 0;
+        for (index, ch) in text.chars().enumerate() {
+            if index > 0 {
+                width += self.letter_space;
+            }
+            width += self.glyph(ch).width as i32;
+        }
+        width
+    }
+}
@@ -1,7 +1,44 @@
-#[derive(Debug)]
+pub mod font;
+pub mod package;
+pub mod sprite;
+pub mod texture;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum DitheringMethod {
     No,
     FS,
     Ord4,
     Ord8,
 }
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ResType {
+    Texture,
+    Font,
+    Sprite,
+    IntMap,
+    ExtMap,
+}
+
+impl ResType {
+    pub fn tag(self) -> u8 {
+        match self {
+            ResType::Texture => 1,
+            ResType::Font => 2,
+            ResType::Sprite => 3,
+            ResType::IntMap => 4,
+            ResType::ExtMap => 5,
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Option<ResType> {
+        match tag {
+            1 => Some(ResType::Texture),
+            2 => Some(ResType::Font),
+            3 => Some(ResType::Sprite),
+            4 => Some(ResType::IntMap),
+            5 => Some(ResType::ExtMap),
+            _ => None,
+        }
+    }
+}
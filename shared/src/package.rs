@@ -0,0 +1,257 @@
+//! Package archive layout, all numbers are little-endian.
+//!
+//! ```text
+//! header:    magic "HFPK", version: u16, reserved: u16, entry_count: u32, directory_offset: u32
+//! data:      entry payloads, back to back
+//! directory: entry_count times
+//!            res_type: u8, path_len: u16, path: [u8; path_len], offset: u32, size: u32
+//! ```
+
+use std::{error::Error, fmt::Display, fs, io, path::Path};
+
+use crate::{ResType, font::Font, sprite::Sprite, texture::Texture};
+
+pub const MAGIC: [u8; 4] = *b"HFPK";
+pub const VERSION: u16 = 1;
+pub const HEADER_SIZE: u32 = 16;
+
+#[derive(Debug)]
+pub enum PackageError {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    BadEntry(String),
+    NotFound(String),
+    WrongType {
+        path: String,
+        expected: ResType,
+        found: ResType,
+    },
+}
+
+impl Display for PackageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PackageError::Io(err) => write!(f, "Can't read package: {}", err),
+            PackageError::BadMagic => write!(f, "Not a package file"),
+            PackageError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported package version {}", version)
+            }
+            PackageError::Truncated => write!(f, "Package data is truncated"),
+            PackageError::BadEntry(reason) => write!(f, "Broken package entry: {}", reason),
+            PackageError::NotFound(path) => write!(f, "Entry \"{}\" not found", path),
+            PackageError::WrongType {
+                path,
+                expected,
+                found,
+            } => {
+                write!(
+                    f,
+                    "Entry \"{}\" is {:?}, expected {:?}",
+                    path, found, expected
+                )
+            }
+        }
+    }
+}
+
+impl Error for PackageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PackageError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PackageError {
+    fn from(err: io::Error) -> Self {
+        PackageError::Io(err)
+    }
+}
+
+/// Little-endian cursor over package bytes, running past the end is a [`PackageError::Truncated`].
+pub(crate) struct ByteReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> ByteReader<'a> {
+        ByteReader { data, pos: 0 }
+    }
+
+    pub(crate) fn bytes(&mut self, count: usize) -> Result<&'a [u8], PackageError> {
+        let end = self.pos.checked_add(count).ok_or(PackageError::Truncated)?;
+        let result = self
+            .data
+            .get(self.pos..end)
+            .ok_or(PackageError::Truncated)?;
+        self.pos = end;
+        Ok(result)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, PackageError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, PackageError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, PackageError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub(crate) fn i32(&mut self) -> Result<i32, PackageError> {
+        Ok(self.u32()? as i32)
+    }
+
+    pub(crate) fn f32(&mut self) -> Result<f32, PackageError> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.pos)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PackageEntry {
+    pub path: String,
+    pub res_type: ResType,
+    offset: u32,
+    size: u32,
+}
+
+impl PackageEntry {
+    /// Last component of the entry path, the object name from the project file.
+    pub fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+
+    /// Destination folder of the entry, `/` for top-level objects.
+    pub fn folder(&self) -> &str {
+        match self.path.rfind('/') {
+            Some(0) | None => "/",
+            Some(pos) => &self.path[..pos],
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+}
+
+pub struct Package {
+    data: Vec<u8>,
+    entries: Vec<PackageEntry>,
+}
+
+impl Package {
+    pub fn open<P: AsRef<Path>>(filename: P) -> Result<Package, PackageError> {
+        Package::from_bytes(fs::read(filename)?)
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> Result<Package, PackageError> {
+        let mut header = ByteReader::new(&data);
+        if header.bytes(4).map_err(|_| PackageError::BadMagic)? != MAGIC {
+            return Err(PackageError::BadMagic);
+        }
+        let version = header.u16()?;
+        if version != VERSION {
+            return Err(PackageError::UnsupportedVersion(version));
+        }
+        header.u16()?;
+        let entry_count = header.u32()?;
+        let directory_offset = header.u32()?;
+
+        let mut directory = ByteReader::new(
+            data.get(directory_offset as usize..)
+                .ok_or(PackageError::Truncated)?,
+        );
+        let mut entries = Vec::new();
+        for _ in 0..entry_count {
+            let tag = directory.u8()?;
+            let path_len = directory.u16()?;
+            let path = String::from_utf8(directory.bytes(path_len as usize)?.to_vec())
+                .map_err(|_| PackageError::BadEntry(String::from("path is not valid UTF-8")))?;
+            let res_type = ResType::from_tag(tag).ok_or_else(|| {
+                PackageError::BadEntry(format!("\"{}\" has unknown type {}", path, tag))
+            })?;
+            let offset = directory.u32()?;
+            let size = directory.u32()?;
+            if offset < HEADER_SIZE || offset as u64 + size as u64 > directory_offset as u64 {
+                return Err(PackageError::BadEntry(format!(
+                    "\"{}\" points outside of the data block",
+                    path
+                )));
+            }
+            entries.push(PackageEntry {
+                path,
+                res_type,
+                offset,
+                size,
+            });
+        }
+        if !directory.is_empty() {
+            return Err(PackageError::BadEntry(String::from(
+                "unexpected data after the directory",
+            )));
+        }
+
+        Ok(Package { data, entries })
+    }
+
+    pub fn entries(&self) -> &[PackageEntry] {
+        &self.entries
+    }
+
+    pub fn find(&self, path: &str) -> Option<&PackageEntry> {
+        self.entries.iter().find(|entry| entry.path == path)
+    }
+
+    pub fn entry_data(&self, entry: &PackageEntry) -> &[u8] {
+        &self.data[entry.offset as usize..(entry.offset + entry.size) as usize]
+    }
+
+    pub fn data(&self, path: &str) -> Result<&[u8], PackageError> {
+        let entry = self
+            .find(path)
+            .ok_or_else(|| PackageError::NotFound(path.to_string()))?;
+        Ok(self.entry_data(entry))
+    }
+
+    pub fn texture(&self, path: &str) -> Result<Texture, PackageError> {
+        Texture::from_bytes(self.typed_data(path, ResType::Texture)?)
+    }
+
+    pub fn font(&self, path: &str) -> Result<Font, PackageError> {
+        Font::from_bytes(self.typed_data(path, ResType::Font)?)
+    }
+
+    pub fn sprite(&self, path: &str) -> Result<Sprite, PackageError> {
+        Sprite::from_bytes(self.typed_data(path, ResType::Sprite)?)
+    }
+
+    fn typed_data(&self, path: &str, expected: ResType) -> Result<&[u8], PackageError> {
+        let entry = self
+            .find(path)
+            .ok_or_else(|| PackageError::NotFound(path.to_string()))?;
+        if entry.res_type != expected {
+            return Err(PackageError::WrongType {
+                path: path.to_string(),
+                expected,
+                found: entry.res_type,
+            });
+        }
+        Ok(self.entry_data(entry))
+    }
+}
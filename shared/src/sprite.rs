@@ -0,0 +1,71 @@
+use crate::{
+    package::{ByteReader, PackageError},
+    texture::Texture,
+};
+
+/// Animated sprite as written by pandora, every frame is a separate RGB565 texture of the same size.
+///
+/// ```text
+/// frame_count: u32, origin_x: i32, origin_y: i32, frame_time: f32,
+/// frames: frame_count times texture
+/// ```
+#[derive(Debug, Clone)]
+pub struct Sprite {
+    /// Hotspot inside the frame, the point placed at the sprite position when drawing.
+    pub origin_x: i32,
+    pub origin_y: i32,
+    /// Seconds each frame stays on screen.
+    pub frame_time: f32,
+    pub frames: Vec<Texture>,
+}
+
+impl Sprite {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Sprite, PackageError> {
+        let mut reader = ByteReader::new(bytes);
+        let frame_count = reader.u32()?;
+        let origin_x = reader.i32()?;
+        let origin_y = reader.i32()?;
+        let frame_time = reader.f32()?;
+        if frame_count == 0 {
+            return Err(PackageError::BadEntry(String::from("sprite has no frames")));
+        }
+        if !frame_time.is_finite() || frame_time <= 0.0 {
+            return Err(PackageError::BadEntry(format!(
+                "bad frame time {}",
+                frame_time
+            )));
+        }
+
+        let mut frames: Vec<Texture> = Vec::new();
+        for _ in 0..frame_count {
+            let frame = Texture::read(&mut reader)?;
+            if frames
+                .first()
+                .is_some_and(|first| first.width != frame.width || first.height != frame.height)
+            {
+                return Err(PackageError::BadEntry(String::from(
+                    "sprite frames differ in size",
+                )));
+            }
+            frames.push(frame);
+        }
+        if !reader.is_empty() {
+            return Err(PackageError::BadEntry(String::from(
+                "unexpected data after sprite frames",
+            )));
+        }
+
+        Ok(Sprite {
+            origin_x,
+            origin_y,
+            frame_time,
+            frames,
+        })
+    }
+
+    /// Frame shown `time` seconds after the animation started, the animation loops.
+    pub fn frame_at(&self, time: f32) -> &Texture {
+        let index = (time / self.frame_time).max(0.0) as usize % self.frames.len();
+        &self.frames[index]
+    }
+}
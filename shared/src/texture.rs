@@ -0,0 +1,122 @@
+use crate::package::{ByteReader, PackageError};
+
+/// RGB565 texture as written by pandora.
+///
+/// ```text
+/// width: u32, height: u32, has_transparent: u8, [transparent_color: u16], pixels: [u16; width * height]
+/// ```
+#[derive(Debug, Clone)]
+pub struct Texture {
+    pub width: u32,
+    pub height: u32,
+    pub transparent_color: Option<u16>,
+    pub data: Vec<u16>,
+}
+
+impl Texture {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Texture, PackageError> {
+        let mut reader = ByteReader::new(bytes);
+        let texture = Texture::read(&mut reader)?;
+        if !reader.is_empty() {
+            return Err(PackageError::BadEntry(String::from(
+                "unexpected data after texture pixels",
+            )));
+        }
+        Ok(texture)
+    }
+
+    pub(crate) fn read(reader: &mut ByteReader) -> Result<Texture, PackageError> {
+        let width = reader.u32()?;
+        let height = reader.u32()?;
+        let transparent_color = match reader.u8()? {
+            0 => None,
+            1 => Some(reader.u16()?),
+            flag => {
+                return Err(PackageError::BadEntry(format!(
+                    "bad transparency flag {}",
+                    flag
+                )));
+            }
+        };
+        let pixel_count = (width as usize)
+            .checked_mul(height as usize)
+            .ok_or(PackageError::Truncated)?;
+        let mut data = Vec::with_capacity(pixel_count.min(reader.remaining() / 2));
+        for _ in 0..pixel_count {
+            data.push(reader.u16()?);
+        }
+        Ok(Texture {
+            width,
+            height,
+            transparent_color,
+            data,
+        })
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> u16 {
+        self.data[(x + y * self.width) as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Texture bytes with the given header and pixels.
+    fn texture_bytes(
+        width: u32,
+        height: u32,
+        transparent_color: Option<u16>,
+        pixels: &[u16],
+    ) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&width.to_le_bytes());
+        data.extend_from_slice(&height.to_le_bytes());
+        match transparent_color {
+            Some(color) => {
+                data.push(1);
+                data.extend_from_slice(&color.to_le_bytes());
+            }
+            None => data.push(0),
+        }
+        for pixel in pixels {
+            data.extend_from_slice(&pixel.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn read_pixels() {
+        let texture =
+            Texture::from_bytes(&texture_bytes(2, 2, Some(0xf81f), &[1, 2, 3, 4])).unwrap();
+        assert_eq!((texture.width, texture.height), (2, 2));
+        assert_eq!(texture.transparent_color, Some(0xf81f));
+        assert_eq!(texture.get(1, 0), 2);
+        assert_eq!(texture.get(0, 1), 3);
+
+        let texture = Texture::from_bytes(&texture_bytes(1, 1, None, &[7])).unwrap();
+        assert_eq!(texture.transparent_color, None);
+    }
+
+    #[test]
+    fn broken_textures() {
+        let errors = [
+            texture_bytes(2, 2, None, &[1, 2, 3]),
+            texture_bytes(1, 1, None, &[1, 2]),
+            texture_bytes(u32::MAX, u32::MAX, None, &[]),
+            vec![1, 0, 0, 0, 1, 0, 0, 0, 2],
+            vec![1, 0],
+        ]
+        .map(|bytes| Texture::from_bytes(&bytes).unwrap_err().to_string());
+        assert_eq!(
+            errors,
+            [
+                "Package data is truncated",
+                "Broken package entry: unexpected data after texture pixels",
+                "Package data is truncated",
+                "Broken package entry: bad transparency flag 2",
+                "Package data is truncated",
+            ]
+        );
+    }
+}